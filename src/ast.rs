@@ -1,8 +1,10 @@
-use crate::tokens::{LiteralValue, Token};
+use std::rc::Rc;
+
+use crate::tokens::{LiteralValue, Span, Token};
 
 
 #[derive(Debug, Clone)]
-pub(crate) enum BinaryOperator {
+pub enum BinaryOperator {
     BangEqual,
     EqualEqual,
     Greater,
@@ -13,22 +15,54 @@ pub(crate) enum BinaryOperator {
     Plus,
     Slash,
     Star,
+    Percent,
+    StarStar,
+}
+
+impl BinaryOperator {
+    /// The operator as it's written in source code.
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            BinaryOperator::BangEqual => "!=",
+            BinaryOperator::EqualEqual => "==",
+            BinaryOperator::Greater => ">",
+            BinaryOperator::GreaterEqual => ">=",
+            BinaryOperator::Less => "<",
+            BinaryOperator::LessEqual => "<=",
+            BinaryOperator::Minus => "-",
+            BinaryOperator::Plus => "+",
+            BinaryOperator::Slash => "/",
+            BinaryOperator::Star => "*",
+            BinaryOperator::Percent => "%",
+            BinaryOperator::StarStar => "**",
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
-pub(crate) enum LogicalOperator {
+pub enum LogicalOperator {
     And,
     Or
 }
 
 #[derive(Debug, Clone)]
-pub(crate) enum UnaryOperator {
+pub enum UnaryOperator {
     Bang,
     Minus,
 }
 
+impl UnaryOperator {
+    /// The operator as it's written in source code.
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            UnaryOperator::Bang => "!",
+            UnaryOperator::Minus => "-",
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
-pub(crate) enum Expr {
+pub enum Expr {
     Binary(Binary),
     Unary(Unary),
     Literal(Literal),
@@ -41,10 +75,42 @@ pub(crate) enum Expr {
     Set(Set),
     This(This),
     Super(Super),
+    List(List),
+    Map(Map),
+    Index(Index),
+    IndexSet(IndexSet),
+    Lambda(Lambda),
+    Interpolation(Interpolation),
+}
+
+impl Expr {
+    /// The region of source code this expression was parsed from.
+    pub fn span(&self) -> Span {
+        match self {
+            Expr::Binary(e) => e.left.span().to(e.right.span()),
+            Expr::Unary(e) => e.token.span.to(e.right.span()),
+            Expr::Literal(e) => e.token.span,
+            Expr::Grouping(e) => e.expr.span(),
+            Expr::Variable(e) => e.token.span,
+            Expr::Assignment(e) => e.token.span.to(e.value.span()),
+            Expr::Logical(e) => e.left.span().to(e.right.span()),
+            Expr::Call(e) => e.callee.span().to(e.token.span),
+            Expr::Get(e) => e.object.span().to(e.name.span),
+            Expr::Set(e) => e.object.span().to(e.value.span()),
+            Expr::This(e) => e.keyword.span,
+            Expr::Super(e) => e.keyword.span.to(e.method.span),
+            Expr::List(e) => e.token.span.to(e.closing.span),
+            Expr::Map(e) => e.token.span.to(e.closing.span),
+            Expr::Index(e) => e.object.span().to(e.token.span),
+            Expr::IndexSet(e) => e.object.span().to(e.value.span()),
+            Expr::Lambda(e) => e.token.span,
+            Expr::Interpolation(e) => e.token.span.to(e.closing.span),
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
-pub(crate) struct Binary {
+pub struct Binary {
     pub token: Token,
     pub operator: BinaryOperator,
     pub left: Box<Expr>,
@@ -52,36 +118,36 @@ pub(crate) struct Binary {
 }
 
 #[derive(Debug, Clone)]
-pub(crate) struct Unary {
+pub struct Unary {
     pub token: Token,
     pub operator: UnaryOperator,
     pub right: Box<Expr>
 }
 
 #[derive(Debug, Clone)]
-pub(crate) struct Literal {
+pub struct Literal {
     pub token: Token,
     pub value: LiteralValue,
 }
 
 #[derive(Debug, Clone)]
-pub(crate) struct Grouping {
+pub struct Grouping {
     pub expr: Box<Expr>
 }
 
 #[derive(Debug, Clone)]
-pub(crate) struct Variable {
+pub struct Variable {
     pub token: Token
 }
 
 #[derive(Debug, Clone)]
-pub(crate) struct Assignment {
+pub struct Assignment {
     pub token: Token,
     pub value: Box<Expr>
 }
 
 #[derive(Debug, Clone)]
-pub(crate) struct Logical {
+pub struct Logical {
     pub token: Token,
     pub operator: LogicalOperator,
     pub left: Box<Expr>,
@@ -89,100 +155,181 @@ pub(crate) struct Logical {
 }
 
 #[derive(Debug, Clone)]
-pub(crate) struct Call {
+pub struct Call {
     pub callee: Box<Expr>,
     pub arguments: Vec<Expr>,
     pub token: Token, // token for closing ")" after call
 }
 
 #[derive(Debug, Clone)]
-pub(crate) struct Get {
+pub struct Get {
     pub object: Box<Expr>,
     pub name: Token,
 }
 
 #[derive(Debug, Clone)]
-pub(crate) struct Set {
+pub struct Set {
     pub object: Box<Expr>,
     pub name: Token,
     pub value: Box<Expr>,
 }
 
 #[derive(Debug, Clone)]
-pub(crate) struct This {
+pub struct This {
     pub keyword: Token,
 }
 
 #[derive(Debug, Clone)]
-pub(crate) struct Super {
+pub struct Super {
     pub keyword: Token,
     pub method: Token,
 }
 
 #[derive(Debug, Clone)]
-pub(crate) enum Statement {
+pub struct List {
+    pub token: Token, // token for opening "["
+    pub elements: Vec<Expr>,
+    pub closing: Token, // token for closing "]"
+}
+
+#[derive(Debug, Clone)]
+pub struct Map {
+    pub token: Token, // token for opening "{"
+    pub entries: Vec<(Expr, Expr)>,
+    pub closing: Token, // token for closing "}"
+}
+
+#[derive(Debug, Clone)]
+pub struct Index {
+    pub object: Box<Expr>,
+    pub index: Box<Expr>,
+    pub token: Token, // token for closing "]"
+}
+
+#[derive(Debug, Clone)]
+pub struct IndexSet {
+    pub object: Box<Expr>,
+    pub index: Box<Expr>,
+    pub value: Box<Expr>,
+    pub token: Token, // token for closing "]"
+}
+
+#[derive(Debug, Clone)]
+pub struct Lambda {
+    pub token: Token, // token for "fun", or "=>" in the arrow form
+    // named "anonymous", an arrow function's body is a return statement
+    pub declaration: Rc<FunDeclStatement>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Interpolation {
+    pub token: Token, // token for the string before the first "${"
+    pub parts: Vec<Expr>, // the string's text and the expressions in it, in order
+    pub closing: Token, // token for the string after the last "}"
+}
+
+#[derive(Debug, Clone)]
+pub enum Statement {
     ExpressionStatement(ExpressionStatement),
     PrintStatement(PrintStatement),
     VarDeclStatement(VarDeclStatement),
     BlockStatement(BlockStatement),
     IfStatement(IfStatement),
     WhileStatement(WhileStatement),
-    FunDeclStatement(FunDeclStatement),
+    // function declarations are shared with the functions created from them, rather than copied
+    FunDeclStatement(Rc<FunDeclStatement>),
     ReturnStatement(ReturnStatement),
     ClassDeclStatement(ClassDeclStatement),
+    BreakStatement(BreakStatement),
+    ContinueStatement(ContinueStatement),
+    TryStatement(TryStatement),
+    ThrowStatement(ThrowStatement),
 }
 
 #[derive(Debug, Clone)]
-pub(crate) struct ExpressionStatement {
+pub struct ExpressionStatement {
     pub expression: Expr
 }
 
 #[derive(Debug, Clone)]
-pub(crate) struct PrintStatement {
+pub struct PrintStatement {
     pub token: Token,
     pub value: Expr
 }
 
 #[derive(Debug, Clone)]
-pub(crate) struct VarDeclStatement {
+pub struct VarDeclStatement {
     pub token: Token,
     pub initializer: Option<Expr>
 }
 
 #[derive(Debug, Clone)]
-pub(crate) struct BlockStatement {
+pub struct BlockStatement {
     pub statements: Vec<Statement>
 }
 
 #[derive(Debug, Clone)]
-pub(crate) struct IfStatement {
+pub struct IfStatement {
     pub condition: Expr,
     pub then_branch: Box<Statement>,
     pub else_branch: Option<Box<Statement>>
 }
 
 #[derive(Debug, Clone)]
-pub(crate) struct WhileStatement {
+pub struct WhileStatement {
     pub condition: Expr,
-    pub body: Box<Statement>
+    pub body: Box<Statement>,
+    // only set for desugared for loops, kept separate from the body so "continue" still runs it
+    pub increment: Option<Expr>,
 }
 
 #[derive(Debug, Clone)]
-pub(crate) struct FunDeclStatement {
+pub struct FunDeclStatement {
     pub name: Token,
     pub parameters: Vec<Token>,
     pub body: Vec<Statement>,
 }
 
 #[derive(Debug, Clone)]
-pub(crate) struct ReturnStatement {
+pub struct ReturnStatement {
     pub keyword: Token,
     pub value: Option<Expr>,
 }
 
 #[derive(Debug, Clone)]
-pub(crate) struct ClassDeclStatement {
+pub struct BreakStatement {
+    pub keyword: Token,
+}
+
+#[derive(Debug, Clone)]
+pub struct ContinueStatement {
+    pub keyword: Token,
+}
+
+#[derive(Debug, Clone)]
+pub struct TryStatement {
+    pub keyword: Token,
+    pub body: Vec<Statement>,
+    pub catch: Option<CatchClause>,
+    pub finally: Option<Vec<Statement>>,
+}
+
+/// The `catch (name) { ... }` part of a try statement.
+#[derive(Debug, Clone)]
+pub struct CatchClause {
+    pub name: Token,
+    pub body: Vec<Statement>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ThrowStatement {
+    pub keyword: Token,
+    pub value: Expr,
+}
+
+#[derive(Debug, Clone)]
+pub struct ClassDeclStatement {
     pub name: Token,
-    pub methods: Vec<FunDeclStatement>,
+    pub methods: Vec<Rc<FunDeclStatement>>,
     pub superclass: Option<Variable>,
 }
\ No newline at end of file
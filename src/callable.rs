@@ -1,14 +1,30 @@
 use std::{cell::RefCell, fmt::{Debug, Display}, rc::Rc};
 
-use crate::{ast::FunDeclStatement, class::LoxInstance, error::{LoxError, LoxErrorKind}, tree_walker::{Environment, TreeWalker, Value}};
+use crate::{ast::FunDeclStatement, chunk::Chunk, class::LoxInstance, error::LoxError, gc::{Heap, HeapRef}, tree_walker::{ControlFlow, Environment, LocalSlot, TreeWalker, Value}, vm::{UpvalueRef, Vm}};
 
-pub(crate) trait LoxCallable: Display + Debug + LoxCallableClone {
+pub trait LoxCallable: Display + Debug + LoxCallableClone {
     fn call(& self, interpreter:  &mut TreeWalker, arguments: Vec<Value>) -> Result<Value, LoxError>;
 
     fn arity(&self) -> usize;
+
+    /// The fewest arguments this can be called with, for natives whose last arguments are optional.
+    fn min_arity(&self) -> usize {
+        self.arity()
+    }
+
+    /// The name shown for calls to this in stack traces.
+    fn name(&self) -> String;
+
+    /// Reports the heap objects this holds on to, for the garbage collector. See `gc::Trace`.
+    fn trace(&self, _visit: &mut dyn FnMut(HeapRef)) {}
+
+    /// The Lox function this is, if it is one, so the VM can call compiled functions without leaving its loop.
+    fn function(&self) -> Option<&Function> {
+        None
+    }
 }
 
-pub(crate) trait LoxCallableClone {
+pub trait LoxCallableClone {
     fn clone_box(&self) -> Box<dyn LoxCallable>;
 }
 
@@ -26,22 +42,71 @@ impl Clone for Box<dyn LoxCallable> {
 }
 
 #[derive(Debug, Clone)]
-pub(crate) struct Function {
-    declaration: FunDeclStatement,
-    closure: Rc<RefCell<Environment>>,
+pub struct Function {
+    declaration: Rc<FunDeclStatement>,
+    closure: Closure,
     is_initializer: bool,
 }
 
+// what a function can see besides its own locals
+#[derive(Debug, Clone)]
+enum Closure {
+    // the environment it was declared in, for functions the tree-walker runs
+    Environment(Rc<RefCell<Environment>>),
+    Compiled(Compiled),
+}
+
+/// A function compiled for the VM: its code, the variables it captured, and the instance it's bound to if it's a method.
+#[derive(Debug, Clone)]
+pub struct Compiled {
+    pub code: Rc<Chunk>,
+    pub upvalues: Vec<UpvalueRef>,
+    pub this: Option<Rc<RefCell<LoxInstance>>>,
+}
+
 impl Function {
-    pub fn new(declaration: FunDeclStatement, closure: Rc<RefCell<Environment>>, is_initializer: bool) -> Function {
-        Function { declaration, closure, is_initializer }
+    pub fn new(declaration: Rc<FunDeclStatement>, closure: Rc<RefCell<Environment>>, is_initializer: bool) -> Function {
+        Function { declaration, closure: Closure::Environment(closure), is_initializer }
+    }
+
+    /// A function whose body runs as bytecode, see `vm::Vm`.
+    pub fn compiled(declaration: Rc<FunDeclStatement>, code: Rc<Chunk>, upvalues: Vec<UpvalueRef>, is_initializer: bool) -> Function {
+        Function { declaration, closure: Closure::Compiled(Compiled { code, upvalues, this: None }), is_initializer }
+    }
+
+    pub fn bind(&self, instance: &Rc<RefCell<LoxInstance>>, heap: &mut Heap) -> Function {
+        let closure = match &self.closure {
+            Closure::Environment(closure) => {
+                let mut environment = Environment::new();
+                environment.parent = Some(Rc::clone(closure));
+                environment.define(Value::InstanceValue(Rc::clone(instance)));
+                Closure::Environment(heap.environment(environment))
+            }
+            // the VM puts the instance in slot 0 when calling it
+            Closure::Compiled(compiled) => Closure::Compiled(Compiled { this: Some(Rc::clone(instance)), ..compiled.clone() }),
+        };
+        Function { declaration: Rc::clone(&self.declaration), closure, is_initializer: self.is_initializer }
+    }
+
+    /// The code and captured variables of a function compiled for the VM.
+    pub fn code(&self) -> Option<&Compiled> {
+        match &self.closure {
+            Closure::Compiled(compiled) => Some(compiled),
+            Closure::Environment(_) => None,
+        }
     }
 
-    pub fn bind(&self, instance: &Rc<RefCell<LoxInstance>>) -> Function {
-        let mut environment = Environment::new();
-        environment.parent = Some(Rc::clone(&self.closure));
-        environment.define("this", Value::InstanceValue(Rc::clone(instance)));
-        return Function::new(self.declaration.clone(), Rc::new(RefCell::new(environment)), self.is_initializer);
+    pub fn is_initializer(&self) -> bool {
+        self.is_initializer
+    }
+
+    // the instance a method is bound to
+    fn this(&self) -> Value {
+        match &self.closure {
+            // it's the only variable in the closure
+            Closure::Environment(closure) => closure.borrow().get_at(LocalSlot { depth: 0, slot: 0 }),
+            Closure::Compiled(compiled) => compiled.this.as_ref().map_or(Value::NilValue, |this| Value::InstanceValue(Rc::clone(this))),
+        }
     }
 }
 
@@ -53,42 +118,38 @@ impl Display for Function {
 
 impl LoxCallable for Function {
 
-    fn call(& self, interpreter:  &mut TreeWalker, mut arguments: Vec<Value>) -> Result<Value, LoxError>{
+    fn call(& self, interpreter:  &mut TreeWalker, arguments: Vec<Value>) -> Result<Value, LoxError>{
+        let closure = match &self.closure {
+            Closure::Environment(closure) => closure,
+            Closure::Compiled(_) => return Vm::new(interpreter).call(self, arguments),
+        };
         let mut env = Environment::new();
-        env.parent = Some(Rc::clone(&self.closure));
+        env.parent = Some(Rc::clone(closure));
         // ASSUMPTION made: arguments.len() = self.declaration.parameters.len()
-        for parameter in &self.declaration.parameters {
-            // get the first item in the list
-            let arg = arguments.remove(0);
-            env.define(&parameter.lexeme, arg)
-        }
+        // the parameters are the function's first locals, in order
+        env.values = arguments;
 
-        let result = interpreter.execute_block(&self.declaration.body, Rc::new(RefCell::new(env)));
+        let env = interpreter.heap.environment(env);
+        let result = interpreter.execute_block(&self.declaration.body, env)?;
         match result {
-            Ok(_) => {
+            ControlFlow::Return(value) => {
                 if self.is_initializer {
-                    self.closure.borrow().get_at("this", 0)
+                    // normally, the value will be Value::Nil
+                    // (since only returns w/o a value are allowed from initializers, ex: "return;" but not "return 5;")
+                    // but we want to special case make sure the initializer always returns a reference to the Value::Instance
+                    // NOTE: this only matters if you call init() directly, not from the class itself.
+                    // ie Foo() doesn't use use this code, but Foo().init() does. yeah, an extremely special case.
+                    Ok(self.this())
                 } else {
-                    Ok(Value::NilValue)
+                    Ok(value)
                 }
             },
-            Err(e) => {
-                match e.kind {
-                    LoxErrorKind::Return(value) => {
-                        if self.is_initializer {
-                            // normally, the value will be Value::Nil
-                            // (since only returns w/o a value are allowed from initializers, ex: "return;" but not "return 5;")
-                            // but we want to special case make sure the initializer always returns a reference to the Value::Instance
-                            // NOTE: this only matters if you call init() directly, not from the class itself.
-                            // ie Foo() doesn't use use this code, but Foo().init() does. yeah, an extremely special case.
-                            self.closure.borrow().get_at("this", 0)
-                        } else {
-                            Ok(value)
-                        }
-                    },
-                    _ => {
-                        Err(e)
-                    }
+            // the resolver doesn't allow break/continue to escape a function body
+            ControlFlow::Normal | ControlFlow::Break | ControlFlow::Continue => {
+                if self.is_initializer {
+                    Ok(self.this())
+                } else {
+                    Ok(Value::NilValue)
                 }
             }
         }
@@ -97,4 +158,26 @@ impl LoxCallable for Function {
     fn arity(&self) -> usize {
         self.declaration.parameters.len()
     }
+
+    fn name(&self) -> String {
+        self.declaration.name.lexeme.to_owned()
+    }
+
+    fn trace(&self, visit: &mut dyn FnMut(HeapRef)) {
+        match &self.closure {
+            Closure::Environment(closure) => visit(HeapRef::Environment(closure)),
+            Closure::Compiled(compiled) => {
+                for upvalue in &compiled.upvalues {
+                    visit(HeapRef::Upvalue(upvalue));
+                }
+                if let Some(this) = &compiled.this {
+                    visit(HeapRef::Instance(this));
+                }
+            }
+        }
+    }
+
+    fn function(&self) -> Option<&Function> {
+        Some(self)
+    }
 }
\ No newline at end of file
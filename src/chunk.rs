@@ -0,0 +1,120 @@
+//! Bytecode produced by `compiler::Compiler` and run by `vm::Vm`.
+
+use std::rc::Rc;
+
+use crate::{ast::{BinaryOperator, FunDeclStatement, UnaryOperator}, tokens::Span, tree_walker::Value};
+
+/// A single VM instruction.
+///
+/// Operands index into the tables of the chunk the instruction is in,
+/// ex: `GetGlobal(2)` reads the global named `chunk.names[2]`.
+/// Jump targets are instruction indices in the same chunk.
+#[derive(Debug, Clone)]
+pub enum Op {
+    Constant(u32),
+    Nil,
+    True,
+    False,
+    Pop,
+    /// Reads a local variable from its slot in the current call frame.
+    GetLocal(u32),
+    SetLocal(u32),
+    /// Reads a variable captured by the running closure, see `vm::Upvalue`.
+    GetUpvalue(u32),
+    SetUpvalue(u32),
+    GetGlobal(u32),
+    SetGlobal(u32),
+    /// Pops a value and declares it as a global.
+    DefineGlobal(u32),
+    /// Drops every local after the first n in the current call frame, closing any upvalues over them.
+    /// Used at the end of a scope, and by jumps out of one.
+    Unwind(u32),
+    GetProperty(u32),
+    SetProperty(u32),
+    /// Pops the superclass and then "this", and pushes the superclass method bound to "this".
+    GetSuper(u32),
+    Index,
+    IndexSet,
+    Binary(BinaryOperator),
+    Unary(UnaryOperator),
+    /// Replaces the top of the stack with whether it's truthy.
+    Truthy,
+    Print,
+    Jump(u32),
+    /// Pops the condition and jumps if it's falsey.
+    JumpIfFalse(u32),
+    /// Jumps if the top of the stack is falsey, otherwise pops it. Used by `and`.
+    JumpIfFalseOrPop(u32),
+    /// Jumps if the top of the stack is truthy, otherwise pops it. Used by `or`.
+    JumpIfTrueOrPop(u32),
+    /// Calls the value below the arguments. Lox functions compiled for the VM get a new call frame,
+    /// whose slot 0 is the callee (or the instance, for methods) and whose next slots are the arguments.
+    Call(u32),
+    Closure(u32),
+    /// Creates a class. If it has a superclass, that's popped from the top of the stack.
+    Class(u32),
+    List(u32),
+    Map(u32),
+    /// Pops that many values and pushes them formatted and joined into one string.
+    Concat(u32),
+    Return,
+    Throw,
+    Try(u32),
+    /// Leaves the chunk because of a `break` whose loop is in an enclosing chunk.
+    Break,
+    Continue,
+}
+
+/// A function declaration inside a chunk, along with its compiled body.
+#[derive(Debug)]
+pub struct FunctionProto {
+    pub declaration: Rc<FunDeclStatement>,
+    pub code: Rc<Chunk>,
+    pub captures: Vec<Capture>, // where each of its upvalues comes from when a closure is created
+}
+
+/// A variable a closure captures from the function it's created in.
+#[derive(Debug, Clone, Copy)]
+pub enum Capture {
+    Local(u32), // a slot in the creating function's call frame
+    Upvalue(u32), // one of the creating function's own upvalues
+}
+
+#[derive(Debug)]
+pub struct ClassProto {
+    pub name: String,
+    pub has_superclass: bool, // if so, the superclass is on the stack
+    pub methods: Vec<FunctionProto>,
+}
+
+/// A try statement. Its blocks are compiled into chunks of their own,
+/// so `Op::Try` can run them and then deal with how they finished.
+/// They run in the same call frame as the try statement, so they can use its locals.
+#[derive(Debug)]
+pub struct TryProto {
+    pub body: Rc<Chunk>,
+    pub catch: Option<Rc<Chunk>>, // the caught value is pushed as its first local
+    pub finally: Option<Rc<Chunk>>,
+    /// Where a `break` or `continue` inside the blocks goes, when the loop it belongs to
+    /// is in the same chunk as the try statement.
+    pub exit: Option<LoopExit>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct LoopExit {
+    pub locals: u32, // in the call frame when the loop started
+    pub break_target: u32,
+    pub continue_target: u32,
+}
+
+/// Compiled code for a script, function body or block.
+#[derive(Debug, Default)]
+pub struct Chunk {
+    pub code: Vec<Op>,
+    pub spans: Vec<Span>, // the source each instruction was compiled from, for errors
+    pub constants: Vec<Value>,
+    pub names: Vec<String>,
+    pub functions: Vec<FunctionProto>,
+    pub classes: Vec<ClassProto>,
+    pub tries: Vec<TryProto>,
+}
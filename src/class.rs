@@ -1,10 +1,10 @@
 use std::{cell::RefCell, collections::HashMap, fmt::Display, rc::Rc};
 
-use crate::{callable::{Function, LoxCallable}, error::{LoxError, LoxErrorKind}, tree_walker::{self, Value}};
+use crate::{callable::{Function, LoxCallable}, error::{LoxError, LoxErrorKind}, gc::{Heap, HeapRef, Trace}, tree_walker::{self, Value}};
 
 
 #[derive(Debug, Clone)]
-pub(crate) struct LoxClass {
+pub struct LoxClass {
     pub name: String,
     methods: HashMap<String, Function>,
     superclass: Option<Rc<LoxClass>>,
@@ -24,6 +24,22 @@ impl LoxClass {
             }
         }
     }
+
+    /// The class's own `init` method, which runs when it's called.
+    pub fn initializer(&self) -> Option<&Function> {
+        self.methods.get("init")
+    }
+}
+
+impl Trace for LoxClass {
+    fn trace(&self, visit: &mut dyn FnMut(HeapRef)) {
+        for method in self.methods.values() {
+            method.trace(visit);
+        }
+        if let Some(superclass) = &self.superclass {
+            visit(HeapRef::Class(superclass));
+        }
+    }
 }
 
 impl Display for LoxClass {
@@ -32,48 +48,68 @@ impl Display for LoxClass {
     }
 }
 
-impl LoxCallable for LoxClass {
-    fn call(& self, interpreter:  &mut tree_walker::TreeWalker, arguments: Vec<tree_walker::Value>) -> Result<tree_walker::Value, LoxError> {
-        let instance = Rc::new(RefCell::new(LoxInstance::new(self.clone())));
-        if let Some(init) = self.methods.get("init") {
-            init.bind(&instance).call(interpreter, arguments)?;
+impl LoxClass {
+    /// Creates an instance, running `init` if the class has one.
+    /// Instances share the class rather than copying it, which is why this needs the `Rc`.
+    pub fn call(self: &Rc<Self>, interpreter:  &mut tree_walker::TreeWalker, arguments: Vec<tree_walker::Value>) -> Result<tree_walker::Value, LoxError> {
+        let instance = interpreter.heap.instance(LoxInstance::new(Rc::clone(self)));
+        if let Some(init) = self.initializer() {
+            init.bind(&instance, &mut interpreter.heap).call(interpreter, arguments)?;
         }
         Ok(Value::InstanceValue(instance))
     }
 
-    fn arity(&self) -> usize {
-        if let Some(init) = self.methods.get("init") {
+    pub fn arity(&self) -> usize {
+        if let Some(init) = self.initializer() {
             init.arity()
         } else {
             0
         }
     }
+
+    pub fn name(&self) -> String {
+        self.name.to_owned()
+    }
 }
 
 #[derive(Debug, Clone)]
-pub(crate) struct LoxInstance {
-    class: LoxClass,
+pub struct LoxInstance {
+    class: Rc<LoxClass>,
     fields: HashMap<String, Value>,
 }
 
 impl LoxInstance {
-    pub fn new(class: LoxClass) -> LoxInstance {
+    pub fn new(class: Rc<LoxClass>) -> LoxInstance {
         LoxInstance { class, fields: HashMap::new() }
     }
 
-    pub fn get(&self, name: &str, instance: &Rc<RefCell<LoxInstance>>) -> Result<Value, LoxError> {
+    pub fn get(&self, name: &str, instance: &Rc<RefCell<LoxInstance>>, heap: &mut Heap) -> Result<Value, LoxError> {
         if let Some(value) = self.fields.get(name) {
             Ok(value.clone())
         } else if let Some(method) = self.class.find_method(name) {
-            Ok(Value::Callable(Box::new(method.bind(instance))))
+            Ok(Value::Callable(Box::new(method.bind(instance, heap))))
         } else {
-            Err(LoxError {kind: LoxErrorKind::AttributeError, message: "Instance has no attribute with that name"})
+            Err(LoxError::new(LoxErrorKind::AttributeError, format!("{} instance has no property '{}'", self.class.name, name)))
         }
     }
 
     pub fn set(&mut self, name: &str, value: Value) {
         self.fields.insert(name.to_owned(), value);
     }
+
+    // drops every field, used by the garbage collector to break reference cycles
+    pub(crate) fn clear(&mut self) {
+        self.fields.clear();
+    }
+}
+
+impl Trace for LoxInstance {
+    fn trace(&self, visit: &mut dyn FnMut(HeapRef)) {
+        visit(HeapRef::Class(&self.class));
+        for value in self.fields.values() {
+            value.trace(visit);
+        }
+    }
 }
 
 impl Display for LoxInstance {
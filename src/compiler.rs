@@ -0,0 +1,524 @@
+//! Compiles resolved syntax trees into bytecode for `vm::Vm`.
+//!
+//! Locals live in slots on the VM's stack rather than in environments. The resolver already
+//! worked out which variables are globals, so the compiler only has to find the slot
+//! (or upvalue, for variables captured from enclosing functions) of each local, the way clox does.
+
+use std::{collections::HashMap, mem, rc::Rc};
+
+use crate::{ast::{ClassDeclStatement, Expr, FunDeclStatement, LogicalOperator, Statement, TryStatement, WhileStatement}, chunk::{Capture, Chunk, ClassProto, FunctionProto, LoopExit, Op, TryProto}, tokens::{LiteralValue, Span, Token}, tree_walker::{LocalSlot, Value}};
+
+pub struct Compiler<'a> {
+    resolved: &'a HashMap<u32, LocalSlot>, // from the resolver, see `TreeWalker::locals`
+    functions: Vec<FunctionState>, // the function being compiled and the ones enclosing it, innermost last
+}
+
+// a function (or the script) being compiled
+struct FunctionState {
+    chunk: Chunk,
+    names: HashMap<String, u32>,
+    locals: Vec<Local>, // in slot order, slot 0 is the callee or "this"
+    captures: Vec<Capture>,
+    scope_depth: u32, // 0 is the top level of the script, where variables are globals
+    loops: Vec<Loop>,
+}
+
+struct Local {
+    name: String,
+    depth: u32,
+}
+
+// a loop whose body is being compiled, and the jumps out of it that need patching once it's done
+struct Loop {
+    locals: u32, // declared when the loop started
+    breaks: Vec<usize>,
+    continues: Vec<usize>,
+    tries: Vec<u32>, // indices of the try statements in its body
+}
+
+impl FunctionState {
+    // `slot_zero` names the first slot, which holds "this" in methods and can't be used otherwise
+    fn new(slot_zero: &str, scope_depth: u32) -> FunctionState {
+        let locals = vec![Local { name: slot_zero.to_owned(), depth: scope_depth }];
+        FunctionState { chunk: Chunk::default(), names: HashMap::new(), locals, captures: Vec::new(), scope_depth, loops: Vec::new() }
+    }
+
+    fn local(&self, name: &str) -> Option<u32> {
+        self.locals.iter().rposition(|local| local.name == name).map(|slot| slot as u32)
+    }
+}
+
+impl<'a> Compiler<'a> {
+    pub fn new(resolved: &'a HashMap<u32, LocalSlot>) -> Compiler<'a> {
+        Compiler { resolved, functions: vec![FunctionState::new("", 0)] }
+    }
+
+    /// Compiles a script, or the part of one the REPL was given.
+    pub fn compile(mut self, statements: &[Statement]) -> Chunk {
+        self.block(statements);
+        self.functions.pop().unwrap().chunk
+    }
+
+    /// Compiles a chunk that returns the value of `expr`.
+    pub fn compile_expression(mut self, expr: &Expr) -> Chunk {
+        self.expression(expr);
+        self.emit(Op::Return, expr.span());
+        self.functions.pop().unwrap().chunk
+    }
+
+    fn current(&mut self) -> &mut FunctionState {
+        self.functions.last_mut().unwrap()
+    }
+
+    fn block(&mut self, statements: &[Statement]) {
+        for statement in statements {
+            self.statement(statement);
+        }
+    }
+
+    fn statement(&mut self, stmt: &Statement) {
+        match stmt {
+            Statement::ExpressionStatement(s) => {
+                self.expression(&s.expression);
+                self.emit(Op::Pop, s.expression.span());
+            }
+            Statement::PrintStatement(s) => {
+                self.expression(&s.value);
+                self.emit(Op::Print, s.token.span);
+            }
+            Statement::VarDeclStatement(s) => {
+                match &s.initializer {
+                    Some(initializer) => self.expression(initializer),
+                    None => { self.emit(Op::Nil, s.token.span); }
+                }
+                self.define_variable(&s.token);
+            }
+            Statement::BlockStatement(s) => {
+                self.begin_scope();
+                self.block(&s.statements);
+                self.end_scope();
+            }
+            Statement::IfStatement(s) => {
+                self.expression(&s.condition);
+                let to_else = self.emit(Op::JumpIfFalse(0), s.condition.span());
+                self.statement(&s.then_branch);
+                match &s.else_branch {
+                    Some(else_branch) => {
+                        let to_end = self.emit(Op::Jump(0), s.condition.span());
+                        self.patch_jump(to_else);
+                        self.statement(else_branch);
+                        self.patch_jump(to_end);
+                    }
+                    None => self.patch_jump(to_else),
+                }
+            }
+            Statement::WhileStatement(s) => self.while_statement(s),
+            Statement::FunDeclStatement(s) => {
+                // declared first, so the function can call itself
+                let is_local = self.declare_local(&s.name);
+                let function = self.function(s, false);
+                self.emit(Op::Closure(function), s.name.span);
+                if !is_local {
+                    let name = self.name(&s.name.lexeme);
+                    self.emit(Op::DefineGlobal(name), s.name.span);
+                }
+            }
+            Statement::ReturnStatement(s) => {
+                match &s.value {
+                    Some(value) => self.expression(value),
+                    None => { self.emit(Op::Nil, s.keyword.span); }
+                }
+                self.emit(Op::Return, s.keyword.span);
+            }
+            Statement::ClassDeclStatement(s) => self.class_declaration(s),
+            Statement::BreakStatement(s) => self.loop_exit(&s.keyword, true),
+            Statement::ContinueStatement(s) => self.loop_exit(&s.keyword, false),
+            Statement::TryStatement(s) => self.try_statement(s),
+            Statement::ThrowStatement(s) => {
+                self.expression(&s.value);
+                self.emit(Op::Throw, s.keyword.span.to(s.value.span()));
+            }
+        }
+    }
+
+    fn begin_scope(&mut self) {
+        self.current().scope_depth += 1;
+    }
+
+    fn end_scope(&mut self) {
+        let current = self.current();
+        current.scope_depth -= 1;
+        let depth = current.scope_depth;
+        let before = current.locals.len();
+        current.locals.retain(|local| local.depth <= depth);
+        let after = current.locals.len() as u32;
+        if after < before as u32 {
+            // dropping locals can't fail, so it doesn't need a real span
+            self.emit(Op::Unwind(after), Span::default());
+        }
+    }
+
+    // makes the value on top of the stack a new local, returning false if it's at the top level and should be a global instead
+    fn declare_local(&mut self, name: &Token) -> bool {
+        let current = self.current();
+        if current.scope_depth == 0 {
+            return false;
+        }
+        let depth = current.scope_depth;
+        current.locals.push(Local { name: name.lexeme.to_owned(), depth });
+        true
+    }
+
+    // declares a variable whose value is on top of the stack
+    fn define_variable(&mut self, name: &Token) {
+        if !self.declare_local(name) {
+            let index = self.name(&name.lexeme);
+            self.emit(Op::DefineGlobal(index), name.span);
+        }
+    }
+
+    fn while_statement(&mut self, stmt: &WhileStatement) {
+        let span = stmt.condition.span();
+        let start = self.current().chunk.code.len();
+        self.expression(&stmt.condition);
+        let to_end = self.emit(Op::JumpIfFalse(0), span);
+        let locals = self.current().locals.len() as u32;
+        self.current().loops.push(Loop { locals, breaks: Vec::new(), continues: Vec::new(), tries: Vec::new() });
+        self.statement(&stmt.body);
+        let continue_target = self.current().chunk.code.len();
+        if let Some(increment) = &stmt.increment {
+            self.expression(increment);
+            self.emit(Op::Pop, increment.span());
+        }
+        self.emit(Op::Jump(start as u32), span);
+        self.patch_jump(to_end);
+
+        let current = self.current();
+        let finished = current.loops.pop().unwrap();
+        let break_target = current.chunk.code.len();
+        for jump in finished.breaks {
+            self.patch_jump_to(jump, break_target);
+        }
+        for jump in finished.continues {
+            self.patch_jump_to(jump, continue_target);
+        }
+        for index in finished.tries {
+            self.current().chunk.tries[index as usize].exit = Some(LoopExit { locals: finished.locals, break_target: break_target as u32, continue_target: continue_target as u32 });
+        }
+    }
+
+    // `break` if `is_break`, otherwise `continue`
+    fn loop_exit(&mut self, keyword: &Token, is_break: bool) {
+        let locals = match self.current().loops.last() {
+            Some(enclosing) => enclosing.locals,
+            None => {
+                // the loop is outside of this chunk, so leave it and let the try statement that ran it jump
+                self.emit(if is_break { Op::Break } else { Op::Continue }, keyword.span);
+                return;
+            }
+        };
+        if self.current().locals.len() as u32 > locals {
+            self.emit(Op::Unwind(locals), keyword.span);
+        }
+        let jump = self.emit(Op::Jump(0), keyword.span);
+        let enclosing = self.current().loops.last_mut().unwrap();
+        if is_break {
+            enclosing.breaks.push(jump);
+        } else {
+            enclosing.continues.push(jump);
+        }
+    }
+
+    fn try_statement(&mut self, stmt: &TryStatement) {
+        let body = self.nested_chunk(None, &stmt.body);
+        let catch = stmt.catch.as_ref().map(|catch| self.nested_chunk(Some(&catch.name), &catch.body));
+        let finally = stmt.finally.as_ref().map(|finally| self.nested_chunk(None, finally));
+        let current = self.current();
+        let index = current.chunk.tries.len() as u32;
+        current.chunk.tries.push(TryProto { body, catch, finally, exit: None });
+        if let Some(enclosing) = current.loops.last_mut() {
+            enclosing.tries.push(index);
+        }
+        self.emit(Op::Try(index), stmt.keyword.span);
+    }
+
+    // compiles a block of a try statement into a chunk of its own, which runs in the same call frame.
+    // `local` is a variable the VM pushes before running it, ex: the caught value.
+    fn nested_chunk(&mut self, local: Option<&Token>, statements: &[Statement]) -> Rc<Chunk> {
+        let current = self.current();
+        let chunk = mem::take(&mut current.chunk);
+        let names = mem::take(&mut current.names);
+        let loops = mem::take(&mut current.loops);
+        self.begin_scope();
+        if let Some(local) = local {
+            self.declare_local(local);
+        }
+        self.block(statements);
+        self.end_scope();
+        let current = self.current();
+        current.names = names;
+        current.loops = loops;
+        Rc::new(mem::replace(&mut current.chunk, chunk))
+    }
+
+    // compiles a function's body into a chunk of its own, returning its index in `functions`
+    fn function(&mut self, declaration: &Rc<FunDeclStatement>, is_method: bool) -> u32 {
+        let proto = self.function_proto(declaration, is_method);
+        let current = self.current();
+        current.chunk.functions.push(proto);
+        current.chunk.functions.len() as u32 - 1
+    }
+
+    fn function_proto(&mut self, declaration: &Rc<FunDeclStatement>, is_method: bool) -> FunctionProto {
+        self.functions.push(FunctionState::new(if is_method { "this" } else { "" }, 1));
+        for parameter in &declaration.parameters {
+            self.declare_local(parameter);
+        }
+        self.block(&declaration.body);
+        let function = self.functions.pop().unwrap();
+        FunctionProto { declaration: Rc::clone(declaration), code: Rc::new(function.chunk), captures: function.captures }
+    }
+
+    fn class_declaration(&mut self, stmt: &ClassDeclStatement) {
+        // a local class gets its slot first, so its methods can capture it
+        let is_local = self.current().scope_depth > 0;
+        if is_local {
+            self.emit(Op::Nil, stmt.name.span);
+            self.declare_local(&stmt.name);
+        }
+        let mut span = stmt.name.span;
+        if let Some(superclass) = &stmt.superclass {
+            self.variable(&superclass.token);
+            // "super" is a local in a scope around the methods, which Op::Class closes
+            self.begin_scope();
+            let depth = self.current().scope_depth;
+            self.current().locals.push(Local { name: String::from("super"), depth });
+            // the only thing that can go wrong is the superclass not being a class
+            span = superclass.token.span;
+        }
+        let methods = stmt.methods.iter().map(|method| self.function_proto(method, true)).collect();
+        let proto = ClassProto { name: stmt.name.lexeme.to_owned(), has_superclass: stmt.superclass.is_some(), methods };
+        let current = self.current();
+        current.chunk.classes.push(proto);
+        let index = current.chunk.classes.len() as u32 - 1;
+        self.emit(Op::Class(index), span);
+        if stmt.superclass.is_some() {
+            let current = self.current();
+            current.locals.pop();
+            current.scope_depth -= 1;
+        }
+        if is_local {
+            let slot = self.current().locals.len() as u32 - 1;
+            self.emit(Op::SetLocal(slot), stmt.name.span);
+            self.emit(Op::Pop, stmt.name.span);
+        } else {
+            let name = self.name(&stmt.name.lexeme);
+            self.emit(Op::DefineGlobal(name), stmt.name.span);
+        }
+    }
+
+    fn expression(&mut self, expr: &Expr) {
+        let span = expr.span();
+        match expr {
+            Expr::Binary(e) => {
+                self.expression(&e.left);
+                self.expression(&e.right);
+                self.emit(Op::Binary(e.operator.clone()), span);
+            }
+            Expr::Unary(e) => {
+                self.expression(&e.right);
+                self.emit(Op::Unary(e.operator.clone()), span);
+            }
+            Expr::Literal(e) => {
+                let op = match &e.value {
+                    LiteralValue::NumberValue(n) => self.constant(Value::NumberValue(*n)),
+                    LiteralValue::StringValue(s) => self.constant(Value::StringValue(s.to_owned())),
+                    LiteralValue::BooleanValue(true) => Op::True,
+                    LiteralValue::BooleanValue(false) => Op::False,
+                    LiteralValue::NilValue => Op::Nil,
+                };
+                self.emit(op, span);
+            }
+            Expr::Grouping(e) => self.expression(&e.expr),
+            Expr::Variable(e) => self.variable(&e.token),
+            Expr::Assignment(e) => {
+                self.expression(&e.value);
+                let op = match self.resolved.get(&e.token.id) {
+                    Some(_) => match self.local(&e.token.lexeme) {
+                        Access::Local(slot) => Op::SetLocal(slot),
+                        Access::Upvalue(index) => Op::SetUpvalue(index),
+                    },
+                    None => Op::SetGlobal(self.name(&e.token.lexeme)),
+                };
+                self.emit(op, span);
+            }
+            Expr::Logical(e) => {
+                self.expression(&e.left);
+                let jump = match e.operator {
+                    LogicalOperator::And => self.emit(Op::JumpIfFalseOrPop(0), span),
+                    LogicalOperator::Or => self.emit(Op::JumpIfTrueOrPop(0), span),
+                };
+                self.expression(&e.right);
+                // like `TreeWalker::visit_logical`, the right operand is converted to a boolean
+                self.emit(Op::Truthy, span);
+                self.patch_jump(jump);
+            }
+            Expr::Call(e) => {
+                self.expression(&e.callee);
+                for argument in &e.arguments {
+                    self.expression(argument);
+                }
+                self.emit(Op::Call(e.arguments.len() as u32), span);
+            }
+            Expr::Get(e) => {
+                self.expression(&e.object);
+                let name = self.name(&e.name.lexeme);
+                self.emit(Op::GetProperty(name), span);
+            }
+            Expr::Set(e) => {
+                self.expression(&e.object);
+                self.expression(&e.value);
+                let name = self.name(&e.name.lexeme);
+                self.emit(Op::SetProperty(name), span);
+            }
+            Expr::This(e) => self.variable(&e.keyword),
+            Expr::Super(e) => {
+                // the resolver only allows "super" inside methods, where "this" and "super" are always locals or upvalues
+                self.named_local("this", span);
+                self.named_local("super", span);
+                let method = self.name(&e.method.lexeme);
+                self.emit(Op::GetSuper(method), span);
+            }
+            Expr::List(e) => {
+                for element in &e.elements {
+                    self.expression(element);
+                }
+                self.emit(Op::List(e.elements.len() as u32), span);
+            }
+            Expr::Map(e) => {
+                for (key, value) in &e.entries {
+                    self.expression(key);
+                    self.expression(value);
+                }
+                self.emit(Op::Map(e.entries.len() as u32), span);
+            }
+            Expr::Index(e) => {
+                self.expression(&e.object);
+                self.expression(&e.index);
+                self.emit(Op::Index, span);
+            }
+            Expr::IndexSet(e) => {
+                self.expression(&e.object);
+                self.expression(&e.index);
+                self.expression(&e.value);
+                self.emit(Op::IndexSet, span);
+            }
+            Expr::Lambda(e) => {
+                let function = self.function(&e.declaration, false);
+                self.emit(Op::Closure(function), span);
+            }
+            Expr::Interpolation(e) => {
+                for part in &e.parts {
+                    self.expression(part);
+                }
+                self.emit(Op::Concat(e.parts.len() as u32), span);
+            }
+        }
+    }
+
+    fn variable(&mut self, token: &Token) {
+        match self.resolved.get(&token.id) {
+            Some(_) => self.named_local(&token.lexeme, token.span),
+            None => {
+                let name = self.name(&token.lexeme);
+                self.emit(Op::GetGlobal(name), token.span);
+            }
+        }
+    }
+
+    fn named_local(&mut self, name: &str, span: Span) {
+        let op = match self.local(name) {
+            Access::Local(slot) => Op::GetLocal(slot),
+            Access::Upvalue(index) => Op::GetUpvalue(index),
+        };
+        self.emit(op, span);
+    }
+
+    // where to find a variable the resolver found to be a local
+    fn local(&mut self, name: &str) -> Access {
+        let innermost = self.functions.len() - 1;
+        match self.functions[innermost].local(name) {
+            Some(slot) => Access::Local(slot),
+            // the resolver checked that it's declared in an enclosing function
+            None => Access::Upvalue(self.upvalue(innermost, name).unwrap()),
+        }
+    }
+
+    // the index of the upvalue for `name` in `self.functions[function]`, adding upvalues to it and the functions between it and the variable as needed
+    fn upvalue(&mut self, function: usize, name: &str) -> Option<u32> {
+        if function == 0 {
+            return None;
+        }
+        let capture = match self.functions[function - 1].local(name) {
+            Some(slot) => Capture::Local(slot),
+            None => Capture::Upvalue(self.upvalue(function - 1, name)?),
+        };
+        let captures = &mut self.functions[function].captures;
+        let index = captures.iter().position(|existing| match (existing, &capture) {
+            (Capture::Local(a), Capture::Local(b)) | (Capture::Upvalue(a), Capture::Upvalue(b)) => a == b,
+            _ => false,
+        });
+        Some(match index {
+            Some(index) => index as u32,
+            None => {
+                captures.push(capture);
+                captures.len() as u32 - 1
+            }
+        })
+    }
+
+    fn constant(&mut self, value: Value) -> Op {
+        let chunk = &mut self.current().chunk;
+        chunk.constants.push(value);
+        Op::Constant(chunk.constants.len() as u32 - 1)
+    }
+
+    // the index of `name` in the chunk's names, adding it if it isn't there yet
+    fn name(&mut self, name: &str) -> u32 {
+        let current = self.current();
+        if let Some(index) = current.names.get(name) {
+            return *index;
+        }
+        let index = current.chunk.names.len() as u32;
+        current.chunk.names.push(name.to_owned());
+        current.names.insert(name.to_owned(), index);
+        index
+    }
+
+    // returns the index of the instruction, for patching jumps
+    fn emit(&mut self, op: Op, span: Span) -> usize {
+        let chunk = &mut self.current().chunk;
+        chunk.code.push(op);
+        chunk.spans.push(span);
+        chunk.code.len() - 1
+    }
+
+    // points the jump at `at` to the next instruction to be emitted
+    fn patch_jump(&mut self, at: usize) {
+        let target = self.current().chunk.code.len();
+        self.patch_jump_to(at, target);
+    }
+
+    fn patch_jump_to(&mut self, at: usize, target: usize) {
+        match &mut self.current().chunk.code[at] {
+            Op::Jump(to) | Op::JumpIfFalse(to) | Op::JumpIfFalseOrPop(to) | Op::JumpIfTrueOrPop(to) => *to = target as u32,
+            op => unreachable!("{:?} isn't a jump", op),
+        }
+    }
+}
+
+// how compiled code reaches a local variable
+enum Access {
+    Local(u32),
+    Upvalue(u32),
+}
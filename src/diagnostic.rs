@@ -0,0 +1,194 @@
+//! Renders errors for humans, in the style of rustc:
+//!
+//! ```text
+//! error[TypeError]: Operands to '+' must be two numbers or two strings, got number and nil
+//!  --> script.lox:2:10
+//!   |
+//! 2 |   return a + b;
+//!   |          ^^^^^
+//!   at add (script.lox:2)
+//!   at <script> (script.lox:5)
+//! ```
+
+use std::fmt::Write;
+
+use crate::{error::LoxError, tokens::Span};
+
+const RED: &str = "\x1b[1;31m";
+const BLUE: &str = "\x1b[1;34m";
+const GREEN: &str = "\x1b[1;32m";
+const BOLD: &str = "\x1b[1m";
+const RESET: &str = "\x1b[0m";
+
+/// Turns `LoxError`s into messages that quote the offending source.
+///
+/// The source must be the same text the errors were produced from,
+/// since spans are byte offsets into it.
+#[derive(Debug, Clone)]
+pub struct Renderer<'a> {
+    file_name: &'a str,
+    source: &'a str,
+    colour: bool,
+}
+
+impl<'a> Renderer<'a> {
+    pub fn new(file_name: &'a str, source: &'a str) -> Renderer<'a> {
+        Renderer { file_name, source, colour: false }
+    }
+
+    /// Enables ANSI colours, which should only be used when writing to a terminal.
+    pub fn with_colour(mut self, colour: bool) -> Renderer<'a> {
+        self.colour = colour;
+        self
+    }
+
+    pub fn render(&self, error: &LoxError) -> String {
+        // every gutter is as wide as the largest line number shown
+        let width = std::iter::once(&error.span)
+            .chain(error.notes.iter().map(|note| &note.span))
+            .flatten()
+            .map(|span| span.line.to_string().len())
+            .max()
+            .unwrap_or(0);
+
+        let mut out = String::new();
+        writeln!(out, "{}: {}", self.paint(RED, &format!("error[{}]", error.kind)), self.paint(BOLD, &error.message)).unwrap();
+        if let Some(span) = error.span {
+            self.snippet(&mut out, span, width, RED);
+        }
+        for note in &error.notes {
+            match note.span {
+                Some(span) => {
+                    writeln!(out, "{}: {}", self.paint(GREEN, "note"), note.message).unwrap();
+                    self.snippet(&mut out, span, width, BLUE);
+                }
+                None => {
+                    writeln!(out, "{:width$} {} {}: {}", "", self.paint(BLUE, "="), self.paint(BOLD, "note"), note.message, width = width).unwrap();
+                }
+            }
+        }
+        for frame in &error.backtrace {
+            writeln!(out, "  at {} ({}:{})", frame.function, self.file_name, frame.span.line).unwrap();
+        }
+        out
+    }
+
+    // writes the location of `span` and, if it can be found, the line it starts on with the span underlined
+    fn snippet(&self, out: &mut String, span: Span, width: usize, underline_colour: &str) {
+        writeln!(out, "{:width$}{} {}:{}", "", self.paint(BLUE, "-->"), self.file_name, span, width = width).unwrap();
+        let (line, start, end) = match self.locate(span) {
+            Some(location) => location,
+            None => return,
+        };
+        let gutter = self.paint(BLUE, "|");
+        writeln!(out, "{:width$} {}", "", gutter, width = width).unwrap();
+        writeln!(out, "{} {} {}", self.paint(BLUE, &format!("{:>width$}", span.line, width = width)), gutter, line).unwrap();
+        // keep tabs so the underline lines up with the text above it
+        let padding: String = line[..start].chars().map(|c| if c == '\t' { '\t' } else { ' ' }).collect();
+        let carets = "^".repeat(line[start..end].chars().count().max(1));
+        writeln!(out, "{:width$} {} {}{}", "", gutter, padding, self.paint(underline_colour, &carets), width = width).unwrap();
+    }
+
+    // finds the source line a span starts on, and the part of that line it covers
+    fn locate(&self, span: Span) -> Option<(&'a str, usize, usize)> {
+        let line_start = self.source.get(..span.start)?.rfind('\n').map_or(0, |i| i + 1);
+        let line_end = self.source[line_start..].find('\n').map_or(self.source.len(), |i| line_start + i);
+        let line = self.source[line_start..line_end].trim_end_matches('\r');
+        // a span can start after the line's trimmed '\r', ex: the end of a file ending in one
+        let start = span.start.min(line_start + line.len());
+        let end = span.end.clamp(start, line_start + line.len());
+        Some((line, start - line_start, end - line_start))
+    }
+
+    fn paint(&self, colour: &str, text: &str) -> String {
+        if self.colour {
+            format!("{}{}{}", colour, text, RESET)
+        } else {
+            text.to_owned()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::interpreter::Interpreter;
+
+    use super::*;
+
+    fn render_first_error(source: &str) -> String {
+        let errors = Interpreter::new().run_source(source).unwrap_err();
+        Renderer::new("test.lox", source).render(&errors[0])
+    }
+
+    #[test]
+    fn underlines_the_offending_expression() {
+        let rendered = render_first_error("var a = 1;\nprint a + \"b\";\n");
+        assert_eq!(rendered, "\
+error[TypeError]: Operands to '+' must be two numbers or two strings, got number and string
+ --> test.lox:2:7
+  |
+2 | print a + \"b\";
+  |       ^^^^^^^
+");
+    }
+
+    #[test]
+    fn notes_point_at_related_code() {
+        let rendered = render_first_error("{\n\tvar a = 1;\n\tvar a = 2;\n}");
+        assert_eq!(rendered, "\
+error[ResolvingError]: Variable 'a' already exists in this scope
+ --> test.lox:3:6
+  |
+3 | \tvar a = 2;
+  | \t    ^
+note: previously declared here
+ --> test.lox:2:6
+  |
+2 | \tvar a = 1;
+  | \t    ^
+");
+    }
+
+    #[test]
+    fn runtime_errors_show_the_calls_that_led_to_them() {
+        let rendered = render_first_error("fun inner(x) {\n  return x + nil;\n}\nfun outer() { return inner(1); }\nouter();");
+        assert!(rendered.ends_with("  |          ^^^^^^^\n  at inner (test.lox:2)\n  at outer (test.lox:4)\n  at <script> (test.lox:5)\n"), "{}", rendered);
+    }
+
+    #[test]
+    fn carriage_returns_at_the_end_of_a_line_are_not_shown() {
+        let rendered = render_first_error("print 1;\r\nprint nil + 1;\r\n");
+        assert_eq!(rendered, "\
+error[TypeError]: Operands to '+' must be two numbers or two strings, got nil and number
+ --> test.lox:2:7
+  |
+2 | print nil + 1;
+  |       ^^^^^^^
+");
+        // the missing ';' is reported after the last '\r', which isn't shown
+        let rendered = render_first_error("print 1\r");
+        assert_eq!(rendered, "\
+error[SyntaxError]: expected ';' after statement, found end of file
+ --> test.lox:1:9
+  |
+1 | print 1
+  |        ^
+");
+        // the scanner doesn't count a lone '\r' as a line break, so neither does the renderer
+        let rendered = render_first_error("print 1;\rprint 2\r");
+        assert_eq!(rendered, "\
+error[SyntaxError]: expected ';' after statement, found end of file
+ --> test.lox:1:18
+  |
+1 | print 1;\rprint 2
+  |                 ^
+");
+    }
+
+    #[test]
+    fn errors_without_a_location_are_just_a_header() {
+        let error = LoxError::new(crate::error::LoxErrorKind::RuntimeError, "failed");
+        let rendered = Renderer::new("test.lox", "").with_colour(true).render(&error);
+        assert_eq!(rendered, "\x1b[1;31merror[RuntimeError]\x1b[0m: \x1b[1mfailed\x1b[0m\n");
+    }
+}
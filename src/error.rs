@@ -1,40 +1,103 @@
-
 use core::fmt;
 use std::error::Error;
 
-use crate::tree_walker::Value;
+use crate::{tokens::Span, tree_walker::Value};
+
+#[derive(Debug)]
+pub struct LoxError {
+    pub message: String,
+    pub kind: LoxErrorKind,
+    pub span: Option<Span>, // where in the source the error happened, if known
+    pub notes: Vec<Note>, // extra context, ex: where a clashing variable was declared
+    pub backtrace: Vec<StackFrame>, // innermost call first, empty unless raised inside a call
+}
 
+/// A secondary message attached to an error, optionally pointing at another part of the source.
 #[derive(Debug)]
-pub(crate) struct LoxError {
-    pub message: &'static str,
-    pub kind: LoxErrorKind
+pub struct Note {
+    pub message: String,
+    pub span: Option<Span>,
+}
+
+/// One function in the chain of calls that led to a runtime error.
+#[derive(Debug, Clone)]
+pub struct StackFrame {
+    pub function: String,
+    pub span: Span, // the point execution had reached in this function
 }
 
 #[derive(Debug)]
-pub(crate) enum LoxErrorKind {
+pub enum LoxErrorKind {
     ScannerError,
-    SyntaxError(i32),
+    SyntaxError,
     TypeError,
     NameError,
     RuntimeError,
     ResolvingError,
     AttributeError,
-    Return(Value), // dirty hack
+    IndexError,
+    KeyError,
+    IOError,
+    Exit(i32), // raised by `exit(code)`, this can't be caught
+    Thrown(Box<Value>), // a value thrown by a `throw` statement
+}
+
+impl LoxError {
+    /// An error with no span, notes or backtrace yet, see the `with_*` methods to add them.
+    pub fn new(kind: LoxErrorKind, message: impl Into<String>) -> LoxError {
+        LoxError { message: message.into(), kind, span: None, notes: Vec::new(), backtrace: Vec::new() }
+    }
+
+    pub fn with_span(mut self, span: Span) -> LoxError {
+        self.span = Some(span);
+        self
+    }
+
+    /// Sets the error's span, unless it already has a more specific one.
+    pub fn with_default_span(mut self, span: Span) -> LoxError {
+        if self.span.is_none() {
+            self.span = Some(span);
+        }
+        self
+    }
+
+    /// Whether a try statement can catch this error. Only exiting can't be.
+    pub fn is_catchable(&self) -> bool {
+        !matches!(self.kind, LoxErrorKind::Exit(_))
+    }
+
+    pub fn with_note(mut self, message: &str, span: Option<Span>) -> LoxError {
+        self.notes.push(Note { message: message.to_owned(), span });
+        self
+    }
 }
 
 impl Error for LoxError {}
 
 impl fmt::Display for LoxError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match &self.kind {
+        match &self.span {
+            Some(span) => write!(f, "{}: {} (line {}, column {})", self.kind, self.message, span.line, span.column),
+            None => write!(f, "{}: {}", self.kind, self.message),
+        }
+    }
+}
+
+impl fmt::Display for LoxErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self {
             LoxErrorKind::ScannerError => write!(f, "ScannerError"),
-            LoxErrorKind::SyntaxError(line) => write!(f, "SyntaxError: line {}", line),
+            LoxErrorKind::SyntaxError => write!(f, "SyntaxError"),
             LoxErrorKind::TypeError => write!(f, "TypeError"),
             LoxErrorKind::NameError => write!(f, "NameError"),
-            LoxErrorKind::Return(_) => write!(f, "ReturnValue"),
             LoxErrorKind::RuntimeError => {write!(f, "RuntimeError")},
             LoxErrorKind::ResolvingError => {write!(f, "ResolvingError")},
             LoxErrorKind::AttributeError => {write!(f, "AttributeError")},
+            LoxErrorKind::IndexError => {write!(f, "IndexError")},
+            LoxErrorKind::KeyError => {write!(f, "KeyError")},
+            LoxErrorKind::IOError => {write!(f, "IOError")},
+            LoxErrorKind::Exit(_) => {write!(f, "Exit")},
+            LoxErrorKind::Thrown(_) => {write!(f, "Exception")},
         }
     }
 }
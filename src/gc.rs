@@ -0,0 +1,379 @@
+//! A cycle collector for the interpreter's reference counted heap.
+//!
+//! Environments, instances, classes, lists, maps and upvalues are shared through `Rc`,
+//! which frees them as soon as nothing points at them, unless they point at each other.
+//! Every one of them is allocated through a [`Heap`], which keeps a weak reference to it.
+//! Collection works by trial deletion: an object with more strong references than the other
+//! heap objects account for is held from outside the heap (by the interpreter or the host),
+//! so it's a root. Anything that can't be reached from a root is garbage, and emptying it
+//! breaks the cycles keeping it alive, which lets `Rc` free it.
+
+use std::{cell::RefCell, collections::HashMap, rc::{Rc, Weak}};
+
+use crate::{class::{LoxClass, LoxInstance}, list::ListRef, map::{LoxMap, MapRef}, tree_walker::{Environment, Value}, vm::{Upvalue, UpvalueRef}};
+
+// the fewest allocations between automatic collections
+const MIN_THRESHOLD: usize = 10_000;
+
+/// A strong reference held by a heap object, as seen by the collector.
+pub enum HeapRef<'a> {
+    Environment(&'a Rc<RefCell<Environment>>),
+    Instance(&'a Rc<RefCell<LoxInstance>>),
+    Class(&'a Rc<LoxClass>),
+    List(&'a ListRef),
+    Map(&'a MapRef),
+    Upvalue(&'a UpvalueRef),
+}
+
+impl HeapRef<'_> {
+    fn address(&self) -> usize {
+        match self {
+            HeapRef::Environment(e) => Rc::as_ptr(e) as *const () as usize,
+            HeapRef::Instance(i) => Rc::as_ptr(i) as *const () as usize,
+            HeapRef::Class(c) => Rc::as_ptr(c) as *const () as usize,
+            HeapRef::List(l) => Rc::as_ptr(l) as *const () as usize,
+            HeapRef::Map(m) => Rc::as_ptr(m) as *const () as usize,
+            HeapRef::Upvalue(u) => Rc::as_ptr(u) as *const () as usize,
+        }
+    }
+}
+
+/// Implemented by everything that can hold references to heap objects.
+///
+/// `trace` must report each strong reference it owns exactly once,
+/// including ones owned indirectly through values that aren't heap objects themselves (like functions).
+pub trait Trace {
+    fn trace(&self, visit: &mut dyn FnMut(HeapRef));
+}
+
+impl Trace for Value {
+    fn trace(&self, visit: &mut dyn FnMut(HeapRef)) {
+        match self {
+            Value::Callable(c) => c.trace(visit),
+            Value::InstanceValue(i) => visit(HeapRef::Instance(i)),
+            Value::ClassValue(c) => visit(HeapRef::Class(c)),
+            Value::ListValue(l) => visit(HeapRef::List(l)),
+            Value::MapValue(m) => visit(HeapRef::Map(m)),
+            Value::NumberValue(_) | Value::StringValue(_) | Value::BooleanValue(_) | Value::NilValue => {}
+        }
+    }
+}
+
+/// Counts of the objects a heap is tracking.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct HeapStats {
+    pub environments: usize,
+    pub instances: usize,
+    pub classes: usize,
+    pub lists: usize,
+    pub maps: usize,
+    pub upvalues: usize,
+    pub collected: usize, // objects freed by the collection that produced these stats
+}
+
+#[derive(Debug)]
+enum Object {
+    Environment(Weak<RefCell<Environment>>),
+    Instance(Weak<RefCell<LoxInstance>>),
+    Class(Weak<LoxClass>),
+    List(Weak<RefCell<Vec<Value>>>),
+    Map(Weak<RefCell<LoxMap>>),
+    Upvalue(Weak<RefCell<Upvalue>>),
+}
+
+impl Object {
+    fn upgrade(&self) -> Option<Live> {
+        match self {
+            Object::Environment(e) => e.upgrade().map(Live::Environment),
+            Object::Instance(i) => i.upgrade().map(Live::Instance),
+            Object::Class(c) => c.upgrade().map(Live::Class),
+            Object::List(l) => l.upgrade().map(Live::List),
+            Object::Map(m) => m.upgrade().map(Live::Map),
+            Object::Upvalue(u) => u.upgrade().map(Live::Upvalue),
+        }
+    }
+}
+
+// an object that was still alive when a collection started
+enum Live {
+    Environment(Rc<RefCell<Environment>>),
+    Instance(Rc<RefCell<LoxInstance>>),
+    Class(Rc<LoxClass>),
+    List(ListRef),
+    Map(MapRef),
+    Upvalue(UpvalueRef),
+}
+
+impl Live {
+    fn as_ref(&self) -> HeapRef<'_> {
+        match self {
+            Live::Environment(e) => HeapRef::Environment(e),
+            Live::Instance(i) => HeapRef::Instance(i),
+            Live::Class(c) => HeapRef::Class(c),
+            Live::List(l) => HeapRef::List(l),
+            Live::Map(m) => HeapRef::Map(m),
+            Live::Upvalue(u) => HeapRef::Upvalue(u),
+        }
+    }
+
+    fn strong_count(&self) -> usize {
+        match self {
+            Live::Environment(e) => Rc::strong_count(e),
+            Live::Instance(i) => Rc::strong_count(i),
+            Live::Class(c) => Rc::strong_count(c),
+            Live::List(l) => Rc::strong_count(l),
+            Live::Map(m) => Rc::strong_count(m),
+            Live::Upvalue(u) => Rc::strong_count(u),
+        }
+    }
+
+    // reports the object's references, or returns false if it's borrowed right now and can't be looked at
+    fn trace(&self, visit: &mut dyn FnMut(HeapRef)) -> bool {
+        match self {
+            Live::Environment(e) => e.try_borrow().map(|e| e.trace(visit)).is_ok(),
+            Live::Instance(i) => i.try_borrow().map(|i| i.trace(visit)).is_ok(),
+            Live::Class(c) => {
+                Trace::trace(c.as_ref(), visit);
+                true
+            }
+            Live::List(l) => l.try_borrow().map(|l| l.iter().for_each(|value| value.trace(visit))).is_ok(),
+            Live::Map(m) => m.try_borrow().map(|m| m.trace(visit)).is_ok(),
+            Live::Upvalue(u) => u.try_borrow().map(|u| u.trace(visit)).is_ok(),
+        }
+    }
+
+    // drops everything a garbage object refers to, breaking the cycle it's part of
+    fn clear(&self) {
+        match self {
+            Live::Environment(e) => {
+                let mut e = e.borrow_mut();
+                e.values.clear();
+                e.globals.clear();
+                e.parent = None;
+            }
+            Live::Instance(i) => i.borrow_mut().clear(),
+            // classes can't be changed, but every cycle through one also goes through an environment or upvalue
+            Live::Class(_) => {}
+            Live::List(l) => l.borrow_mut().clear(),
+            Live::Map(m) => m.borrow_mut().clear(),
+            Live::Upvalue(u) => *u.borrow_mut() = Upvalue::Closed(Value::NilValue),
+        }
+    }
+}
+
+/// Allocates heap objects and collects the ones that are only kept alive by reference cycles.
+#[derive(Debug)]
+pub struct Heap {
+    objects: Vec<Object>,
+    allocations: usize, // since the last collection
+    threshold: usize,
+}
+
+impl Default for Heap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Heap {
+    pub fn new() -> Heap {
+        Heap { objects: Vec::new(), allocations: 0, threshold: MIN_THRESHOLD }
+    }
+
+    pub fn environment(&mut self, environment: Environment) -> Rc<RefCell<Environment>> {
+        let environment = Rc::new(RefCell::new(environment));
+        self.track(Object::Environment(Rc::downgrade(&environment)));
+        environment
+    }
+
+    pub fn instance(&mut self, instance: LoxInstance) -> Rc<RefCell<LoxInstance>> {
+        let instance = Rc::new(RefCell::new(instance));
+        self.track(Object::Instance(Rc::downgrade(&instance)));
+        instance
+    }
+
+    pub fn class(&mut self, class: LoxClass) -> Rc<LoxClass> {
+        let class = Rc::new(class);
+        self.track(Object::Class(Rc::downgrade(&class)));
+        class
+    }
+
+    pub fn list(&mut self, elements: Vec<Value>) -> ListRef {
+        let list = Rc::new(RefCell::new(elements));
+        self.track(Object::List(Rc::downgrade(&list)));
+        list
+    }
+
+    pub fn map(&mut self, map: LoxMap) -> MapRef {
+        let map = Rc::new(RefCell::new(map));
+        self.track(Object::Map(Rc::downgrade(&map)));
+        map
+    }
+
+    pub fn upvalue(&mut self, upvalue: Upvalue) -> UpvalueRef {
+        let upvalue = Rc::new(RefCell::new(upvalue));
+        self.track(Object::Upvalue(Rc::downgrade(&upvalue)));
+        upvalue
+    }
+
+    fn track(&mut self, object: Object) {
+        self.objects.push(object);
+        self.allocations += 1;
+    }
+
+    /// Whether enough has been allocated since the last collection that it's worth running another.
+    pub fn collection_due(&self) -> bool {
+        self.allocations >= self.threshold
+    }
+
+    /// Counts the objects that are still alive, including unreachable ones that haven't been collected yet.
+    pub fn stats(&self) -> HeapStats {
+        let mut stats = HeapStats::default();
+        for object in &self.objects {
+            match object {
+                Object::Environment(e) if e.strong_count() > 0 => stats.environments += 1,
+                Object::Instance(i) if i.strong_count() > 0 => stats.instances += 1,
+                Object::Class(c) if c.strong_count() > 0 => stats.classes += 1,
+                Object::List(l) if l.strong_count() > 0 => stats.lists += 1,
+                Object::Map(m) if m.strong_count() > 0 => stats.maps += 1,
+                Object::Upvalue(u) if u.strong_count() > 0 => stats.upvalues += 1,
+                _ => {}
+            }
+        }
+        stats
+    }
+
+    /// Frees every object that can only be reached through reference cycles.
+    pub fn collect(&mut self) -> HeapStats {
+        let live: Vec<Live> = self.objects.iter().filter_map(Object::upgrade).collect();
+        let index: HashMap<usize, usize> = live.iter().enumerate().map(|(i, object)| (object.as_ref().address(), i)).collect();
+
+        // count the references each object gets from other heap objects
+        let mut internal = vec![0; live.len()];
+        let mut roots = Vec::new();
+        for (i, object) in live.iter().enumerate() {
+            let traced = object.trace(&mut |reference| {
+                if let Some(&j) = index.get(&reference.address()) {
+                    internal[j] += 1;
+                }
+            });
+            if !traced {
+                // something is using it right now, so it's definitely reachable
+                roots.push(i);
+            }
+        }
+        // any other references come from outside the heap, ignoring the one in `live`
+        for (i, object) in live.iter().enumerate() {
+            if object.strong_count() - 1 > internal[i] {
+                roots.push(i);
+            }
+        }
+
+        let mut reachable = vec![false; live.len()];
+        while let Some(i) = roots.pop() {
+            if reachable[i] {
+                continue;
+            }
+            reachable[i] = true;
+            live[i].trace(&mut |reference| {
+                if let Some(&j) = index.get(&reference.address()) {
+                    if !reachable[j] {
+                        roots.push(j);
+                    }
+                }
+            });
+        }
+
+        let mut collected = 0;
+        for (object, reachable) in live.iter().zip(&reachable) {
+            if !reachable {
+                object.clear();
+                collected += 1;
+            }
+        }
+        // dropping `live` frees the garbage, whose cycles are now broken
+        drop(live);
+
+        self.objects.retain(|object| object.upgrade().is_some());
+        self.allocations = 0;
+        self.threshold = MIN_THRESHOLD.max(self.objects.len());
+        HeapStats { collected, ..self.stats() }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{interpreter::Interpreter, output::Recorder};
+
+    #[test]
+    fn collects_unreachable_cycles() {
+        let mut lox = Interpreter::new();
+        lox.run_source("
+            class Node {}
+            fun makeCycle() {
+                var a = Node();
+                var b = Node();
+                a.other = b;
+                b.other = a;
+            }
+            fun makeClosureCycle() {
+                var node = Node();
+                fun method() { return node; }
+                node.method = method;
+            }
+            for (var i = 0; i < 10; i = i + 1) {
+                makeCycle();
+                makeClosureCycle();
+            }
+        ").unwrap();
+        assert_eq!(lox.heap_stats().instances, 30);
+
+        let stats = lox.collect_garbage();
+        assert_eq!(stats.instances, 0);
+        assert!(stats.collected >= 30);
+        assert_eq!(lox.collect_garbage().collected, 0);
+    }
+
+    #[test]
+    fn collects_automatically_while_scripts_run() {
+        let mut lox = Interpreter::new();
+        lox.run_source("
+            class Node {}
+            for (var i = 0; i < 20000; i = i + 1) {
+                var a = Node();
+                a.self = a;
+            }
+        ").unwrap();
+        assert!(lox.heap_stats().instances < 20000);
+    }
+
+    #[test]
+    fn keeps_everything_reachable() {
+        let recorder = Recorder::new();
+        let mut lox = Interpreter::with_output(recorder.clone());
+        lox.run_source("
+            class Node {
+                init(name) { this.name = name; }
+                describe() { return this.name; }
+            }
+            var keep = Node(\"kept\");
+            keep.self = keep;
+            var list = [keep];
+            list.push(list);
+            fun counter() {
+                var count = 0;
+                fun increment() { count = count + 1; return count; }
+                return increment;
+            }
+            var next = counter();
+            next();
+        ").unwrap();
+        let before = lox.heap_stats();
+        let after = lox.collect_garbage();
+        assert_eq!(after.collected, 0);
+        assert_eq!((after.instances, after.lists, after.classes), (before.instances, before.lists, before.classes));
+
+        lox.run_source("print keep.self.describe(); print list[1][0].name; print next();").unwrap();
+        assert_eq!(recorder.outputted(), vec!["kept", "kept", "2"]);
+    }
+}
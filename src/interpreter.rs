@@ -0,0 +1,451 @@
+use std::rc::Rc;
+
+use crate::{ast::Statement, compiler::Compiler, error::LoxError, gc::HeapStats, io::Capabilities, native::NativeFunction, output::OutputSink, parse::Parser, repl, resolver::Resolver, scan::Scanner, tree_walker::{ControlFlow, TreeWalker, Value}, vm::Vm};
+
+/// Runs Lox source code: scan → parse → resolve → execute.
+///
+/// State (globals, functions, classes) persists between calls,
+/// so an `Interpreter` can be fed a script piece by piece, the way the REPL does.
+#[derive(Debug)]
+pub struct Interpreter {
+    tree_walker: TreeWalker,
+    scanner: Scanner,
+    backend: Backend,
+}
+
+/// How an `Interpreter` executes code once it's been resolved.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    /// Walks the syntax tree directly.
+    TreeWalker,
+    /// Compiles the syntax tree to bytecode and runs that on a stack machine.
+    Vm,
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Interpreter::new()
+    }
+}
+
+impl Interpreter {
+    pub fn new() -> Interpreter {
+        Interpreter { tree_walker: TreeWalker::new(), scanner: Scanner::new(), backend: Backend::TreeWalker }
+    }
+
+    /// Creates an interpreter whose `print` statements go to `output` instead of stdout.
+    pub fn with_output<O: OutputSink + 'static>(output: O) -> Interpreter {
+        Interpreter { tree_walker: TreeWalker::new_from_outputter(Box::new(output)), scanner: Scanner::new(), backend: Backend::TreeWalker }
+    }
+
+    /// Switches how later code is executed.
+    ///
+    /// Both backends share globals and the heap, so anything defined before switching is still usable afterwards.
+    pub fn set_backend(&mut self, backend: Backend) {
+        self.backend = backend;
+    }
+
+    pub fn backend(&self) -> Backend {
+        self.backend
+    }
+
+    /// Limits what scripts can do outside of the interpreter, ex: `Capabilities::none()`
+    /// stops them from reading stdin or touching the filesystem.
+    pub fn set_capabilities(&mut self, capabilities: Capabilities) {
+        self.tree_walker.capabilities = capabilities;
+    }
+
+    pub fn capabilities(&self) -> Capabilities {
+        self.tree_walker.capabilities
+    }
+
+    /// Sets the list scripts get from calling `args()`, ex: the command line arguments after the script's name.
+    pub fn set_args(&mut self, args: Vec<String>) {
+        self.tree_walker.args = args;
+    }
+
+    /// Routes the output of every later `print` statement to `output`.
+    pub fn set_output<O: OutputSink + 'static>(&mut self, output: O) {
+        self.tree_walker.outputter = Box::new(output);
+    }
+
+    /// Runs every statement in `source`.
+    ///
+    /// Scanning, parsing and resolving errors are all collected and returned together,
+    /// and nothing is executed if any occur.
+    /// A runtime error stops execution at the statement that caused it,
+    /// and so does a script calling `exit(code)`, which is returned as a `LoxErrorKind::Exit` error.
+    pub fn run_source(&mut self, source: &str) -> Result<(), Vec<LoxError>> {
+        let statements = self.compile(source)?;
+        match self.backend {
+            Backend::TreeWalker => {
+                for statement in statements {
+                    self.tree_walker.visit_statement(&statement).map_err(|e| vec![e])?;
+                }
+            }
+            Backend::Vm => {
+                let chunk = Compiler::new(&self.tree_walker.locals).compile(&statements);
+                Vm::new(&mut self.tree_walker).execute(Rc::new(chunk)).map_err(|e| vec![e])?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether `source` stops partway through a statement, so a REPL should keep reading lines
+    /// before passing it to `run_interactive`, ex: an unclosed `{`.
+    pub fn is_incomplete(&self, source: &str) -> bool {
+        repl::is_incomplete(source)
+    }
+
+    /// Runs `source` the way a REPL would: if it's a single expression (with or without a trailing `;`)
+    /// its value is returned so it can be shown, otherwise it's run like `run_source`.
+    pub fn run_interactive(&mut self, source: &str) -> Result<Option<Value>, Vec<LoxError>> {
+        match repl::lone_expression(source) {
+            Some(expression) => self.eval_expression(expression).map(Some),
+            None => self.run_source(source).map(|_| None),
+        }
+    }
+
+    /// Evaluates a single expression (no trailing `;`) and returns its value.
+    pub fn eval_expression(&mut self, source: &str) -> Result<Value, Vec<LoxError>> {
+        self.scanner.scan(source).map_err(|e| vec![e])?;
+        let mut parser = Parser::new();
+        let expr = match parser.parse_expression(&self.scanner.tokens) {
+            Ok(expr) => expr,
+            Err(_) => return Err(parser.errors),
+        };
+        let mut resolver = Resolver::new(&mut self.tree_walker);
+        resolver.resolve_expression(&expr);
+        if !resolver.errors.is_empty() {
+            return Err(resolver.errors);
+        }
+        match self.backend {
+            Backend::TreeWalker => self.tree_walker.visit_expr(&expr).map_err(|e| vec![e]),
+            Backend::Vm => {
+                let chunk = Compiler::new(&self.tree_walker.locals).compile_expression(&expr);
+                match Vm::new(&mut self.tree_walker).execute(Rc::new(chunk)).map_err(|e| vec![e])? {
+                    ControlFlow::Return(value) => Ok(value),
+                    // the chunk always ends by returning the expression's value
+                    _ => unreachable!(),
+                }
+            }
+        }
+    }
+
+    /// Looks up a global variable, returning `None` if it isn't defined.
+    pub fn get_global(&self, name: &str) -> Option<Value> {
+        self.tree_walker.globals.borrow().globals.get(name).cloned()
+    }
+
+    /// Defines (or redefines) a global variable visible to every script run afterwards.
+    pub fn define_global(&mut self, name: &str, value: Value) {
+        self.tree_walker.globals.borrow_mut().define_global(name, value);
+    }
+
+    /// Exposes a Rust closure to scripts as a global function called `name`.
+    ///
+    /// Scripts calling it with a number of arguments other than `arity` get a `TypeError`,
+    /// so the closure can rely on `arguments.len() == arity`.
+    pub fn define_native<F>(&mut self, name: &str, arity: usize, function: F)
+    where F: Fn(Vec<Value>) -> Result<Value, LoxError> + 'static {
+        let native = NativeFunction::new(name, arity, function);
+        self.define_global(name, Value::Callable(Box::new(native)));
+    }
+
+    /// Frees objects that scripts can no longer reach but that are kept alive by reference cycles,
+    /// ex: two instances that refer to each other.
+    ///
+    /// This also happens automatically as scripts allocate, but hosts can call it at convenient
+    /// points, like between runs of a long-lived script.
+    pub fn collect_garbage(&mut self) -> HeapStats {
+        self.tree_walker.heap.collect()
+    }
+
+    /// Counts the environments, instances, classes, lists and maps that are currently alive.
+    pub fn heap_stats(&self) -> HeapStats {
+        self.tree_walker.heap.stats()
+    }
+
+    fn compile(&mut self, source: &str) -> Result<Vec<Statement>, Vec<LoxError>> {
+        self.scanner.scan(source).map_err(|e| vec![e])?;
+        let mut parser = Parser::new();
+        let statements = match parser.parse(&self.scanner.tokens) {
+            Ok(statements) => statements,
+            Err(_) => return Err(parser.errors),
+        };
+        let mut resolver = Resolver::new(&mut self.tree_walker);
+        resolver.resolve(&statements);
+        if !resolver.errors.is_empty() {
+            return Err(resolver.errors);
+        }
+        Ok(statements)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, rc::Rc};
+
+    use crate::{error::{LoxError, LoxErrorKind}, output::{Recorder, WriteSink}};
+
+    use super::*;
+
+    #[test]
+    fn native_closure_is_callable_from_scripts() {
+        let mut lox = Interpreter::new();
+        lox.define_native("double", 1, |arguments| {
+            match arguments[0] {
+                Value::NumberValue(n) => Ok(Value::NumberValue(n * 2.0)),
+                _ => Err(LoxError::new(LoxErrorKind::TypeError, "double expects a number"))
+            }
+        });
+        lox.run_source("var x = double(21);").unwrap();
+        match lox.get_global("x") {
+            Some(Value::NumberValue(n)) => assert_eq!(n, 42.0),
+            other => panic!("unexpected value {:?}", other),
+        }
+        assert_eq!(format!("{}", lox.eval_expression("double").unwrap()), "<native fn double>");
+    }
+
+    #[test]
+    fn native_closure_can_capture_host_state() {
+        let calls = Rc::new(RefCell::new(Vec::new()));
+        let recorded = Rc::clone(&calls);
+        let mut lox = Interpreter::new();
+        lox.define_native("log", 2, move |arguments| {
+            recorded.borrow_mut().push(format!("{} {}", arguments[0], arguments[1]));
+            Ok(Value::NilValue)
+        });
+        lox.run_source("log(\"a\", 1); log(\"b\", true);").unwrap();
+        assert_eq!(*calls.borrow(), vec!["a 1", "b true"]);
+    }
+
+    #[test]
+    fn native_errors_and_arity_are_reported() {
+        let mut lox = Interpreter::new();
+        lox.define_native("fail", 0, |_| Err(LoxError::new(LoxErrorKind::RuntimeError, "failed")));
+        let errors = lox.run_source("fail();").unwrap_err();
+        assert_eq!(errors[0].message, "failed");
+        let errors = lox.run_source("fail(1);").unwrap_err();
+        assert!(matches!(errors[0].kind, LoxErrorKind::TypeError));
+    }
+
+    #[test]
+    fn print_goes_to_configured_output() {
+        let recorder = Recorder::new();
+        let mut lox = Interpreter::with_output(recorder.clone());
+        lox.run_source("print 1 + 2; print \"three\";").unwrap();
+        assert_eq!(recorder.outputted(), vec!["3", "three"]);
+
+        let buffer = Rc::new(RefCell::new(Vec::new()));
+        lox.set_output(WriteSink::new(SharedBuffer(Rc::clone(&buffer))));
+        lox.run_source("print nil;").unwrap();
+        assert_eq!(String::from_utf8(buffer.borrow().clone()).unwrap(), "nil\n");
+        assert_eq!(recorder.outputted().len(), 2);
+    }
+
+    #[test]
+    fn loop_control_outside_loop_is_rejected() {
+        let mut lox = Interpreter::new();
+        let errors = lox.run_source("break;").unwrap_err();
+        assert!(matches!(errors[0].kind, LoxErrorKind::ResolvingError));
+        let errors = lox.run_source("while (true) { fun f() { continue; } }").unwrap_err();
+        assert!(matches!(errors[0].kind, LoxErrorKind::ResolvingError));
+    }
+
+    #[test]
+    fn named_lambdas_are_rejected() {
+        let mut lox = Interpreter::new();
+        let errors = lox.run_source("var f = fun named() {};").unwrap_err();
+        assert!(matches!(errors[0].kind, LoxErrorKind::SyntaxError));
+        assert_eq!(errors[0].message, "anonymous functions cannot be named");
+        let span = errors[0].span.unwrap();
+        assert_eq!((span.line, span.column), (1, 13));
+    }
+
+    #[test]
+    fn interpolations_need_an_expression_and_a_closing_brace() {
+        let mut lox = Interpreter::new();
+        for source in ["print \"${}\";", "print \"a${}b\" \"c\";", "print \"a${1}b${}c\";"] {
+            let errors = lox.run_source(source).unwrap_err();
+            assert_eq!(errors[0].message, "expected expression inside '${}', found '}'", "{}", source);
+        }
+        // a string literal straight after the expression isn't the rest of the interpolated string
+        let errors = lox.run_source("print \"a${1 \"b\"}c\";").unwrap_err();
+        assert_eq!(errors[0].message, "expected '}' after interpolated expression, found '\"b\"'");
+    }
+
+    #[test]
+    fn errors_point_at_their_source() {
+        let mut lox = Interpreter::new();
+        let errors = lox.run_source("var a = 1;\nprint a + \"b\";").unwrap_err();
+        let span = errors[0].span.unwrap();
+        assert_eq!((span.line, span.column), (2, 7));
+        assert_eq!((span.start, span.end), (17, 24));
+
+        let errors = lox.run_source("var x = 1;\nvar y = @;").unwrap_err();
+        let span = errors[0].span.unwrap();
+        assert_eq!((span.line, span.column), (2, 9));
+
+        let errors = lox.run_source("print nope;").unwrap_err();
+        let span = errors[0].span.unwrap();
+        assert_eq!((span.start, span.end), (6, 10));
+    }
+
+    #[test]
+    fn runtime_errors_record_the_call_stack() {
+        let mut lox = Interpreter::new();
+        lox.define_native("fail", 0, |_| Err(LoxError::new(LoxErrorKind::RuntimeError, "failed")));
+        let errors = lox.run_source("class A {\n  init() { fail(); }\n}\nfun make() {\n  return A();\n}\nmake();").unwrap_err();
+        let frames: Vec<(&str, usize)> = errors[0].backtrace.iter().map(|f| (f.function.as_str(), f.span.line)).collect();
+        assert_eq!(frames, vec![("fail", 2), ("A", 2), ("make", 5), ("<script>", 7)]);
+
+        // errors outside of any call have no backtrace
+        let errors = lox.run_source("print nil + 1;").unwrap_err();
+        assert!(errors[0].backtrace.is_empty());
+    }
+
+    #[test]
+    fn error_messages_name_the_values_involved() {
+        let mut lox = Interpreter::new();
+        let message = |lox: &mut Interpreter, source: &str| lox.run_source(source).unwrap_err().remove(0).message;
+        assert_eq!(message(&mut lox, "print cuont;"), "Undefined variable 'cuont'");
+        assert_eq!(message(&mut lox, "print 1 + nil;"), "Operands to '+' must be two numbers or two strings, got number and nil");
+        assert_eq!(message(&mut lox, "print -\"a\";"), "Operand to '-' must be a number, got string");
+        assert_eq!(message(&mut lox, "fun f(a) {} f(1, 2);"), "f expected 1 arguments but got 2");
+        assert_eq!(message(&mut lox, "class A {} print A().x;"), "A instance has no property 'x'");
+        assert_eq!(message(&mut lox, "print {\"a\": 1}[\"b\"];"), "Map has no entry with key \"b\"");
+        assert_eq!(message(&mut lox, "var x = (1;"), "expected ')' after expression, found ';'");
+        assert_eq!(message(&mut lox, "var x = $;"), "unexpected character '$'");
+    }
+
+    #[test]
+    fn uncaught_throws_are_reported_with_the_value() {
+        let mut lox = Interpreter::new();
+        let errors = lox.run_source("throw [1, 2];").unwrap_err();
+        match &errors[0].kind {
+            LoxErrorKind::Thrown(value) => assert_eq!(value.type_name(), "list"),
+            other => panic!("unexpected error kind {:?}", other),
+        }
+        assert_eq!(errors[0].message, "[1, 2]");
+        let errors = lox.run_source("try { print 1; }").unwrap_err();
+        assert!(matches!(errors[0].kind, LoxErrorKind::SyntaxError));
+    }
+
+    #[test]
+    fn scripts_can_read_and_write_files() {
+        let dir = std::env::temp_dir().join(format!("lox-io-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let recorder = Recorder::new();
+        let mut lox = Interpreter::with_output(recorder.clone());
+        lox.define_global("dir", Value::StringValue(dir.to_string_lossy().into_owned()));
+        lox.run_source("
+            var path = dir + \"/notes.txt\";
+            print exists(path);
+            writeFile(path, \"one\\n\");
+            appendFile(path, \"two\");
+            print readFile(path).split(\"\\n\");
+            print exists(path);
+            print listDir(dir);
+            try {
+                readFile(dir + \"/missing.txt\");
+            } catch (e) {
+                print e.kind;
+            }
+        ").unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(recorder.outputted(), vec!["false", "[\"one\", \"two\"]", "true", "[\"notes.txt\"]", "IOError"]);
+    }
+
+    #[test]
+    fn capabilities_can_disable_io() {
+        let mut lox = Interpreter::new();
+        lox.set_capabilities(Capabilities { filesystem: false, ..Capabilities::default() });
+        for backend in [Backend::TreeWalker, Backend::Vm] {
+            lox.set_backend(backend);
+            let errors = lox.run_source("readFile(\"Cargo.toml\");").unwrap_err();
+            assert!(matches!(errors[0].kind, LoxErrorKind::IOError));
+            assert_eq!(errors[0].message, "Can't call readFile, filesystem access is disabled");
+        }
+        lox.set_capabilities(Capabilities::none());
+        let errors = lox.run_source("readLine();").unwrap_err();
+        assert_eq!(errors[0].message, "Can't call readLine, reading from stdin is disabled");
+    }
+
+    #[test]
+    fn input_prompt_is_optional() {
+        let mut lox = Interpreter::new();
+        lox.set_capabilities(Capabilities::none());
+        for source in ["input();", "input(\"name? \");"] {
+            let errors = lox.run_source(source).unwrap_err();
+            assert_eq!(errors[0].message, "Can't call input, reading from stdin is disabled");
+        }
+        let errors = lox.run_source("input(\"a\", \"b\");").unwrap_err();
+        assert_eq!(errors[0].message, "input expected 0 to 1 arguments but got 2");
+    }
+
+    #[test]
+    fn prompts_go_to_the_output_sink() {
+        let buffer = Rc::new(RefCell::new(Vec::new()));
+        let mut sink = WriteSink::new(SharedBuffer(Rc::clone(&buffer)));
+        sink.output_prompt("name? ").unwrap();
+        sink.output_value(&Value::NumberValue(1.0)).unwrap();
+        assert_eq!(String::from_utf8(buffer.borrow().clone()).unwrap(), "name? 1\n");
+
+        let mut recorder = Recorder::new();
+        recorder.output_prompt("name? ").unwrap();
+        assert_eq!(recorder.outputted(), vec!["name? "]);
+    }
+
+    #[test]
+    fn exit_stops_the_script_and_cant_be_caught() {
+        let recorder = Recorder::new();
+        let mut lox = Interpreter::with_output(recorder.clone());
+        lox.set_args(vec![String::from("first"), String::from("second")]);
+        for backend in [Backend::TreeWalker, Backend::Vm] {
+            lox.set_backend(backend);
+            let errors = lox.run_source("
+                print args();
+                try {
+                    exit(3);
+                } catch (e) {
+                    print \"caught\";
+                } finally {
+                    print \"finally\";
+                }
+                print \"after\";
+            ").unwrap_err();
+            assert!(matches!(errors[0].kind, LoxErrorKind::Exit(3)));
+        }
+        assert_eq!(recorder.outputted(), vec!["[\"first\", \"second\"]", "finally", "[\"first\", \"second\"]", "finally"]);
+        let errors = lox.run_source("exit(1.5);").unwrap_err();
+        assert!(matches!(errors[0].kind, LoxErrorKind::TypeError));
+    }
+
+    #[test]
+    fn interactive_runs_return_lone_expressions() {
+        let recorder = Recorder::new();
+        let mut lox = Interpreter::with_output(recorder.clone());
+        for backend in [Backend::TreeWalker, Backend::Vm] {
+            lox.set_backend(backend);
+            assert!(matches!(lox.run_interactive("var x = 2;"), Ok(None)));
+            assert!(matches!(lox.run_interactive("x * 3"), Ok(Some(Value::NumberValue(n))) if n == 6.0));
+            assert!(matches!(lox.run_interactive("x * 4;"), Ok(Some(Value::NumberValue(n))) if n == 8.0));
+            assert!(matches!(lox.run_interactive("x = 5;"), Ok(None)));
+            assert!(matches!(lox.run_interactive("print x;"), Ok(None)));
+        }
+        assert_eq!(recorder.outputted(), vec!["5", "5"]);
+    }
+
+    struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+    impl std::io::Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+}
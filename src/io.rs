@@ -0,0 +1,127 @@
+use std::{fs, io::{BufRead, Write}, path::Path};
+
+use crate::{error::{LoxError, LoxErrorKind}, native::NativeFunction, tree_walker::{TreeWalker, Value}};
+
+/// What scripts are allowed to do outside of the interpreter.
+///
+/// Everything is allowed by default. Embedders running untrusted scripts can turn capabilities off,
+/// and the natives that need them then fail with an `IOError`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Capabilities {
+    pub stdin: bool, // input() and readLine()
+    pub filesystem: bool, // readFile(), writeFile(), appendFile(), exists() and listDir()
+    pub environment: bool, // env()
+}
+
+impl Default for Capabilities {
+    fn default() -> Self {
+        Capabilities { stdin: true, filesystem: true, environment: true }
+    }
+}
+
+impl Capabilities {
+    /// No access to anything outside the interpreter.
+    pub fn none() -> Capabilities {
+        Capabilities { stdin: false, filesystem: false, environment: false }
+    }
+}
+
+/// The natives for console and file input and output, ex: `readFile`, to be defined as globals.
+pub fn natives() -> Vec<NativeFunction> {
+    vec![
+        stdin("input", 1, |interpreter, arguments| {
+            if let Some(prompt) = arguments.first() {
+                let prompt = string_argument("input", prompt)?;
+                interpreter.outputter.output_prompt(prompt)?;
+            }
+            read_line()
+        }).with_min_arity(0),
+        stdin("readLine", 0, |_, _| read_line()),
+        filesystem("readFile", 1, |_, arguments| {
+            let path = string_argument("readFile", &arguments[0])?;
+            match fs::read_to_string(path) {
+                Ok(contents) => Ok(Value::StringValue(contents)),
+                Err(e) => Err(io_error(format!("Couldn't read file '{}': {}", path, e))),
+            }
+        }),
+        filesystem("writeFile", 2, |_, arguments| {
+            let path = string_argument("writeFile", &arguments[0])?;
+            let contents = string_argument("writeFile", &arguments[1])?;
+            fs::write(path, contents).map_err(|e| io_error(format!("Couldn't write file '{}': {}", path, e)))?;
+            Ok(Value::NilValue)
+        }),
+        filesystem("appendFile", 2, |_, arguments| {
+            let path = string_argument("appendFile", &arguments[0])?;
+            let contents = string_argument("appendFile", &arguments[1])?;
+            fs::OpenOptions::new().append(true).create(true).open(path)
+                .and_then(|mut file| file.write_all(contents.as_bytes()))
+                .map_err(|e| io_error(format!("Couldn't append to file '{}': {}", path, e)))?;
+            Ok(Value::NilValue)
+        }),
+        filesystem("exists", 1, |_, arguments| {
+            let path = string_argument("exists", &arguments[0])?;
+            Ok(Value::BooleanValue(Path::new(path).exists()))
+        }),
+        filesystem("listDir", 1, |interpreter, arguments| {
+            // entry names, sorted so scripts behave the same on every platform
+            let path = string_argument("listDir", &arguments[0])?;
+            let mut entries = fs::read_dir(path)
+                .and_then(|entries| entries.map(|entry| entry.map(|entry| entry.file_name().to_string_lossy().into_owned())).collect::<Result<Vec<String>, _>>())
+                .map_err(|e| io_error(format!("Couldn't list directory '{}': {}", path, e)))?;
+            entries.sort();
+            let names = entries.into_iter().map(Value::StringValue).collect();
+            Ok(Value::ListValue(interpreter.heap.list(names)))
+        }),
+    ]
+}
+
+// a native that fails unless the interpreter allows reading from stdin
+fn stdin<F>(name: &'static str, arity: usize, function: F) -> NativeFunction
+where F: Fn(&mut TreeWalker, Vec<Value>) -> Result<Value, LoxError> + 'static {
+    NativeFunction::with_runtime(name, arity, move |interpreter, arguments| {
+        if !interpreter.capabilities.stdin {
+            return Err(disabled(name, "reading from stdin"));
+        }
+        function(interpreter, arguments)
+    })
+}
+
+// a native that fails unless the interpreter allows filesystem access
+fn filesystem<F>(name: &'static str, arity: usize, function: F) -> NativeFunction
+where F: Fn(&mut TreeWalker, Vec<Value>) -> Result<Value, LoxError> + 'static {
+    NativeFunction::with_runtime(name, arity, move |interpreter, arguments| {
+        if !interpreter.capabilities.filesystem {
+            return Err(disabled(name, "filesystem access"));
+        }
+        function(interpreter, arguments)
+    })
+}
+
+fn disabled(function: &str, capability: &str) -> LoxError {
+    io_error(format!("Can't call {}, {} is disabled", function, capability))
+}
+
+fn io_error(message: String) -> LoxError {
+    LoxError::new(LoxErrorKind::IOError, message)
+}
+
+fn string_argument<'a>(function: &str, argument: &'a Value) -> Result<&'a str, LoxError> {
+    match argument {
+        Value::StringValue(s) => Ok(s),
+        other => Err(LoxError::new(LoxErrorKind::TypeError, format!("Arguments to {} must be strings, got {}", function, other.type_name())))
+    }
+}
+
+// reads a line from stdin without its line ending, or nil at the end of input
+fn read_line() -> Result<Value, LoxError> {
+    let mut line = String::new();
+    match std::io::stdin().lock().read_line(&mut line) {
+        Ok(0) => Ok(Value::NilValue),
+        Ok(_) => {
+            let trimmed = line.trim_end_matches(['\n', '\r']).len();
+            line.truncate(trimmed);
+            Ok(Value::StringValue(line))
+        }
+        Err(e) => Err(io_error(format!("Couldn't read from stdin: {}", e))),
+    }
+}
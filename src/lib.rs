@@ -0,0 +1,137 @@
+//! A tree-walking interpreter for the Lox language described in
+//! [crafting interpreters](https://www.craftinginterpreters.com/).
+//!
+//! Most users only need [`Interpreter`], which runs the whole
+//! scan → parse → resolve → execute pipeline:
+//!
+//! ```
+//! use lox_tree::{Interpreter, Value};
+//!
+//! let mut lox = Interpreter::new();
+//! lox.run_source("var greeting = \"hello\";").unwrap();
+//! match lox.eval_expression("greeting + \" world\"").unwrap() {
+//!     Value::StringValue(s) => assert_eq!(s, "hello world"),
+//!     other => panic!("unexpected value {}", other),
+//! }
+//! ```
+
+// Variant names like `Value::NumberValue` are part of the public API, and `TokenType::EOF` follows the book
+#![allow(clippy::enum_variant_names, clippy::upper_case_acronyms)]
+// `LoxError` carries its span, notes and backtrace inline; errors are rare enough that their size doesn't matter
+#![allow(clippy::result_large_err)]
+
+pub(crate) mod scan;
+pub(crate) mod tokens;
+pub(crate) mod parse;
+pub(crate) mod error;
+pub mod diagnostic;
+pub(crate) mod ast;
+pub(crate) mod tree_walker;
+pub(crate) mod chunk;
+pub(crate) mod compiler;
+pub(crate) mod vm;
+pub(crate) mod callable;
+pub mod output;
+pub(crate) mod native;
+pub(crate) mod math;
+pub(crate) mod io;
+pub(crate) mod process;
+pub(crate) mod resolver;
+pub(crate) mod class;
+pub(crate) mod list;
+pub(crate) mod map;
+pub(crate) mod string;
+pub(crate) mod gc;
+pub(crate) mod repl;
+pub(crate) mod interpreter;
+
+pub use error::{LoxError, LoxErrorKind};
+pub use interpreter::{Backend, Interpreter};
+pub use io::Capabilities;
+pub use tree_walker::Value;
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::{interpreter::{Backend, Interpreter}, output::Recorder};
+
+    // runs the program at `path` and checks it prints what its leading comments say it should
+    fn run_program_test(path: &str, backend: Backend) {
+        // read file
+        let contents = fs::read_to_string(path)
+                .expect("Something went wrong reading the file");
+        let lines:Vec<&str> = contents.split("\n").collect();
+        let mut output = Vec::new();
+        // parse expected output from comments at the start of .lox file
+        for line in lines {
+            if line.len() < 3 || &line[0..2] != "//" {
+                break;
+            }
+            output.push(String::from(&line[2..]))
+        }
+        // set up interpreter for running the test program
+        let recorder = Recorder::new();
+        let mut interpreter = Interpreter::with_output(recorder.clone());
+        interpreter.set_backend(backend);
+
+        if let Err(errors) = interpreter.run_source(&contents) {
+            panic!("error running program: {:?}", errors);
+        }
+
+        assert_eq!(output, recorder.outputted());
+    }
+
+    // every program is run by both backends
+    macro_rules! program_tests {
+        ($($name:ident: $value:expr,)*) => {
+        $(
+            mod $name {
+                use super::*;
+
+                #[test]
+                fn tree_walker() {
+                    run_program_test($value, Backend::TreeWalker);
+                }
+
+                #[test]
+                fn vm() {
+                    run_program_test($value, Backend::Vm);
+                }
+            }
+        )*
+        }
+    }
+
+    program_tests!(
+        basic_operation: "tests/basic_operation.lox",
+        basic_function: "tests/basic_function.lox",
+        less_fun: "tests/less_fun.lox",
+        fun_in_for: "tests/fun_in_for.lox",
+        function_value: "tests/function_value.lox",
+        recursive_fib: "tests/recursive_fib.lox",
+        closure: "tests/closure.lox",
+        print_clock: "tests/print_clock.lox",
+        scoping: "tests/scoping.lox",
+        class_creation: "tests/class_test.lox",
+        class_fields: "tests/class_fields.lox",
+        basic_methods: "tests/basic_methods.lox",
+        basic_init: "tests/basic_init.lox",
+        weird_init: "tests/weird_init.lox",
+        early_return: "tests/early_return.lox",
+        init_early_return: "tests/init_early_return.lox",
+        basic_inheritance: "tests/basic_inheritance.lox",
+        superclass_method: "tests/superclass_method.lox",
+        super_resolving: "tests/super_resolving.lox",
+        lists: "tests/lists.lox",
+        maps: "tests/maps.lox",
+        break_continue: "tests/break_continue.lox",
+        exceptions: "tests/exceptions.lox",
+        local_slots: "tests/local_slots.lox",
+        lambdas: "tests/lambdas.lox",
+        string_escapes: "tests/string_escapes.lox",
+        string_interpolation: "tests/string_interpolation.lox",
+        string_methods: "tests/string_methods.lox",
+        math: "tests/math.lox",
+    );
+}
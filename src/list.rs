@@ -0,0 +1,137 @@
+use std::{cell::RefCell, fmt::Display, rc::Rc};
+
+use crate::{callable::LoxCallable, error::{LoxError, LoxErrorKind}, gc::HeapRef, tree_walker::{TreeWalker, Value}};
+
+/// Storage behind a `Value::ListValue`. Lists are shared, not copied,
+/// so every variable holding the same list sees mutations made through any of them.
+pub type ListRef = Rc<RefCell<Vec<Value>>>;
+
+#[derive(Debug, Clone)]
+enum ListMethodKind {
+    Push,
+    Pop,
+    Len,
+    Insert,
+    Remove,
+    Slice,
+}
+
+/// A native method bound to a particular list, ex: the value of `list.push`
+#[derive(Debug, Clone)]
+pub struct ListMethod {
+    list: ListRef,
+    kind: ListMethodKind,
+}
+
+/// Looks up a method on a list, as in `list.push(1)`
+pub fn get_method(list: &ListRef, name: &str) -> Result<Value, LoxError> {
+    let kind = match name {
+        "push" => ListMethodKind::Push,
+        "pop" => ListMethodKind::Pop,
+        "len" => ListMethodKind::Len,
+        "insert" => ListMethodKind::Insert,
+        "remove" => ListMethodKind::Remove,
+        "slice" => ListMethodKind::Slice,
+        _ => return Err(LoxError::new(LoxErrorKind::AttributeError, format!("List has no method '{}'", name)))
+    };
+    Ok(Value::Callable(Box::new(ListMethod { list: Rc::clone(list), kind })))
+}
+
+/// Converts a Lox value to an index into a list of length `len`.
+/// Valid indexes are whole numbers in `0..len`.
+pub fn list_index(index: &Value, len: usize) -> Result<usize, LoxError> {
+    match index {
+        Value::NumberValue(n) => {
+            if n.fract() != 0.0 {
+                Err(LoxError::new(LoxErrorKind::TypeError, format!("List index must be a whole number, got {}", n)))
+            } else if *n < 0.0 || *n >= len as f64 {
+                Err(LoxError::new(LoxErrorKind::IndexError, format!("List index {} is out of range", n)))
+            } else {
+                Ok(*n as usize)
+            }
+        }
+        other => Err(LoxError::new(LoxErrorKind::TypeError, format!("List index must be a number, got {}", other.type_name())))
+    }
+}
+
+// like list_index, but for positions between elements (used by insert and slice)
+// so `len` itself is allowed
+fn list_position(position: &Value, len: usize) -> Result<usize, LoxError> {
+    list_index(position, len + 1)
+}
+
+impl LoxCallable for ListMethod {
+    fn call(& self, interpreter: &mut TreeWalker, mut arguments: Vec<Value>) -> Result<Value, LoxError> {
+        let mut list = self.list.borrow_mut();
+        match self.kind {
+            ListMethodKind::Push => {
+                list.push(arguments.remove(0));
+                Ok(Value::NilValue)
+            }
+            ListMethodKind::Pop => {
+                match list.pop() {
+                    Some(value) => Ok(value),
+                    None => Err(LoxError::new(LoxErrorKind::IndexError, "Can't pop from an empty list"))
+                }
+            }
+            ListMethodKind::Len => {
+                Ok(Value::NumberValue(list.len() as f64))
+            }
+            ListMethodKind::Insert => {
+                let position = list_position(&arguments[0], list.len())?;
+                list.insert(position, arguments.remove(1));
+                Ok(Value::NilValue)
+            }
+            ListMethodKind::Remove => {
+                let index = list_index(&arguments[0], list.len())?;
+                Ok(list.remove(index))
+            }
+            ListMethodKind::Slice => {
+                let start = list_position(&arguments[0], list.len())?;
+                let end = list_position(&arguments[1], list.len())?;
+                if start > end {
+                    return Err(LoxError::new(LoxErrorKind::IndexError, format!("Slice start {} must not be after its end {}", start, end)))
+                }
+                Ok(Value::ListValue(interpreter.heap.list(list[start..end].to_vec())))
+            }
+        }
+    }
+
+    fn arity(&self) -> usize {
+        match self.kind {
+            ListMethodKind::Push => 1,
+            ListMethodKind::Pop => 0,
+            ListMethodKind::Len => 0,
+            ListMethodKind::Insert => 2,
+            ListMethodKind::Remove => 1,
+            ListMethodKind::Slice => 2,
+        }
+    }
+
+    fn name(&self) -> String {
+        format!("list.{}", self.method_name())
+    }
+
+    fn trace(&self, visit: &mut dyn FnMut(HeapRef)) {
+        visit(HeapRef::List(&self.list));
+    }
+}
+
+impl ListMethod {
+    fn method_name(&self) -> &'static str {
+        match self.kind {
+            ListMethodKind::Push => "push",
+            ListMethodKind::Pop => "pop",
+            ListMethodKind::Len => "len",
+            ListMethodKind::Insert => "insert",
+            ListMethodKind::Remove => "remove",
+            ListMethodKind::Slice => "slice",
+        }
+    }
+}
+
+impl Display for ListMethod {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "<native fn list.{}>", self.method_name())
+    }
+}
@@ -3,192 +3,155 @@ use std::fs;
 use std::io;
 use std::process;
 
-use error::LoxError;
-use io::Write;
-use resolver::Resolver;
-use scan::Scanner;
-use tree_walker::TreeWalker;
+use io::IsTerminal;
+use lox_tree::{diagnostic::Renderer, Backend, Interpreter, LoxError, LoxErrorKind, Value};
+use rustyline::{error::ReadlineError, DefaultEditor};
+
+const USAGE: &str = "usage: rlox [--vm] [script [arguments...]]";
+
+// exit codes, mostly following sysexits.h
+const EXIT_RESOLVING_ERROR: i32 = 63; // sysexits.h has nothing for this, so it's just below the codes it uses
+const EXIT_USAGE: i32 = 64;
+const EXIT_SYNTAX_ERROR: i32 = 65;
+const EXIT_RUNTIME_ERROR: i32 = 70;
+const EXIT_IO_ERROR: i32 = 74;
+
+// the exit code for a script that failed with an error of `kind`
+fn exit_code(kind: &LoxErrorKind) -> i32 {
+    match kind {
+        LoxErrorKind::ScannerError | LoxErrorKind::SyntaxError => EXIT_SYNTAX_ERROR,
+        LoxErrorKind::ResolvingError => EXIT_RESOLVING_ERROR,
+        LoxErrorKind::Exit(code) => *code,
+        _ => EXIT_RUNTIME_ERROR,
+    }
+}
 
-mod scan;
-mod tokens;
-mod parse;
-mod error;
-mod ast;
-mod tree_walker;
-mod callable;
-mod output;
-mod native;
-mod resolver;
-mod class;
+// where the REPL keeps the lines entered in it, so they can be recalled in later sessions
+fn history_path() -> Option<std::path::PathBuf> {
+    env::var_os("HOME").map(|home| std::path::Path::new(&home).join(".lox_history"))
+}
 
-struct Interpreter {
-    had_error: bool,
-    tree_walker: tree_walker::TreeWalker,
-    scanner: scan::Scanner,
+struct Lox {
+    interpreter: Interpreter,
 }
 
-impl Interpreter {
+impl Lox {
 
-    pub fn new() -> Interpreter {
-        return Interpreter { had_error: false, tree_walker: TreeWalker::new(), scanner: Scanner::new() };
+    pub fn new(backend: Backend) -> Lox {
+        let mut interpreter = Interpreter::new();
+        interpreter.set_backend(backend);
+        Lox { interpreter }
     }
 
-    fn run_file(&mut self, filename: &String) {
-        let contents = fs::read_to_string(filename)
-            .expect("Something went wrong reading the file");
-        self.run(&contents);
-        if self.had_error {
-            process::exit(1);
+    fn run_file(&mut self, filename: &str) {
+        let contents = match fs::read_to_string(filename) {
+            Ok(contents) => contents,
+            Err(error) => {
+                eprintln!("error: couldn't read '{}': {}", filename, error);
+                process::exit(EXIT_IO_ERROR);
+            }
+        };
+        if let Err(errors) = self.interpreter.run_source(&contents) {
+            process::exit(self.report(filename, &contents, &errors));
         }
     }
 
     fn run_prompt(&mut self) {
         println!("Welcome to Lox REPL!");
+        let mut editor = match DefaultEditor::new() {
+            Ok(editor) => editor,
+            Err(error) => {
+                eprintln!("error: couldn't start the REPL: {}", error);
+                process::exit(EXIT_IO_ERROR);
+            }
+        };
+        let history = history_path();
+        if let Some(path) = &history {
+            // there's no history the first time the REPL is run
+            let _ = editor.load_history(path);
+        }
+        // lines of a statement that hasn't been finished yet
         let mut input = String::new();
-        let stdin = io::stdin();
         loop {
-            print!("> ");
-            io::stdout().flush().ok().expect("Couldn't flush stdout");
-            input.clear();
-            let read = stdin.read_line(&mut input);
-            match read {
-                Ok(chars_read) => { 
-                    if chars_read == 0 {
-                        break;
+            let prompt = if input.is_empty() { "> " } else { "... " };
+            match editor.readline(prompt) {
+                Ok(line) => {
+                    input.push_str(&line);
+                    input.push('\n');
+                    if input.trim().is_empty() {
+                        input.clear();
+                        continue;
                     }
-                    self.run(&input);
-                    // last run had error, but new run may be fine
-                    self.had_error = false;
-                }
-                Err(error) => println!("error: {}", error),
-            }
-        }
-    }
-
-    fn run<'b>(&mut self, input: &'b String) {
-        match self.scanner.scan(&input) {
-            Ok(_) => {
-                let mut parser = parse::Parser::new();
-                let parsed = parser.parse(&self.scanner.tokens);
-                match parsed {
-                    Ok(statements) => {
-
-                        let mut resolver = Resolver::new(&mut self.tree_walker);
-                        resolver.resolve(&statements);
-                        if resolver.errors.len() > 0 {
-                            for error in resolver.errors {
-                                self.error(error);
-                            }
-                            return;
-                        }
-                        for statement in statements {
-                            let interpreted = self.tree_walker.visit_statement(&statement);
-                            match interpreted {
-                                Ok(_) => {},
-                                Err(e) => {
-                                    self.error(e);
-                                    break;
-                                }
+                    if self.interpreter.is_incomplete(&input) {
+                        continue;
+                    }
+                    let _ = editor.add_history_entry(input.trim_end());
+                    if let Some(path) = &history {
+                        let _ = editor.save_history(path);
+                    }
+                    match self.interpreter.run_interactive(&input) {
+                        // like python, expressions are echoed unless they're nil
+                        Ok(Some(Value::NilValue)) | Ok(None) => {},
+                        Ok(Some(value)) => println!("{}", value.repr()),
+                        Err(errors) => {
+                            let code = self.report("<repl>", &input, &errors);
+                            if let LoxErrorKind::Exit(_) = errors[0].kind {
+                                process::exit(code);
                             }
-                        }
-                        
-                    },
-                    Err(_) => {
-                        for error in parser.errors {
-                            self.error(error);
-                        }
+                            // otherwise the last run had an error, but the next one may be fine
+                        },
                     }
+                    input.clear();
                 }
-
-            },
-            Err(e) => self.error(e)
+                // Ctrl-C throws away what's been typed so far
+                Err(ReadlineError::Interrupted) => input.clear(),
+                Err(ReadlineError::Eof) => break,
+                Err(error) => {
+                    eprintln!("error: {}", error);
+                    break;
+                }
+            }
         }
     }
 
-    fn error(&mut self, error: LoxError) {
-        println!("{:?}", error);
-        self.had_error = true;
+    // reports the errors from running `input`, returning the exit code they call for
+    fn report(&self, file_name: &str, input: &str, errors: &[LoxError]) -> i32 {
+        if let LoxErrorKind::Exit(_) = errors[0].kind {
+            // the script asked to exit, which isn't an error to report
+            return exit_code(&errors[0].kind);
+        }
+        let colour = io::stderr().is_terminal() && env::var_os("NO_COLOR").is_none();
+        let renderer = Renderer::new(file_name, input).with_colour(colour);
+        for error in errors {
+            eprint!("{}", renderer.render(error));
+        }
+        exit_code(&errors[0].kind)
     }
-    
-}                                                 
 
-fn main() {
-    let args: Vec<String> = env::args().collect();
-    let mut lox = Interpreter::new();
-    if args.len() > 2 {
-        panic!("usage: rlox [script]");
-    }
-    else if args.len() == 2 {
-        lox.run_file(&args[1]);
-    }
-    else {
-        lox.run_prompt();
-    }
 }
 
-
-macro_rules! program_tests {
-    ($($name:ident: $value:expr,)*) => {
-    $(
-        #[test]
-        fn $name() {
-            use output::Recorder;
-            // read file
-            let contents = fs::read_to_string($value)
-                    .expect("Something went wrong reading the file");
-            let lines:Vec<&str> = contents.split("\n").collect();
-            let mut output = Vec::new();
-            // parse expected output from comments at the start of .lox file
-            for line in lines {
-                if line.len() < 3 || &line[0..2] != "//" {
-                    break;
-                }
-                output.push(String::from(&line[2..]))
-            }
-            // set up interpreter for running the test program
-            let outputter = Recorder{outputted: Vec::new()};
-            let mut interpreter = TreeWalker::new_from_outputter(outputter);
-            
-            // standard interpreter run
-            let mut scanner = scan::Scanner::new();
-            scanner.scan(&contents).expect("scan error");
-            let mut parser = parse::Parser::new();
-            let statements = parser.parse(&scanner.tokens).expect("parse errors");
-            let mut resolver = Resolver::new(&mut interpreter);
-            resolver.resolve(&statements);
-            if resolver.errors.len() > 0 {
-               panic!("error resolving")
-            }
-            
-            for statement in statements {
-                let interpreted = interpreter.visit_statement(&statement);
-                interpreted.expect("runtime error");
+fn main() {
+    let mut args = env::args().skip(1).peekable();
+    // options come before the script, everything after it is passed to the script
+    let mut backend = Backend::TreeWalker;
+    while let Some(arg) = args.peek() {
+        match arg.as_str() {
+            // runs code on the bytecode VM instead of the tree-walker
+            "--vm" => backend = Backend::Vm,
+            option if option.starts_with("--") => {
+                eprintln!("error: unknown option '{}'\n{}", option, USAGE);
+                process::exit(EXIT_USAGE);
             }
-
-            assert_eq!(output, interpreter.outputter.outputted);
+            _ => break,
+        }
+        args.next();
+    }
+    let mut lox = Lox::new(backend);
+    match args.next() {
+        Some(script) => {
+            lox.interpreter.set_args(args.collect());
+            lox.run_file(&script);
         }
-    )*
+        None => lox.run_prompt(),
     }
 }
-
-program_tests!(
-    basic_operation: "tests/basic_operation.lox",
-    basic_function: "tests/basic_function.lox",
-    less_fun: "tests/less_fun.lox",
-    fun_in_for: "tests/fun_in_for.lox",
-    function_value: "tests/function_value.lox",
-    recursive_fib: "tests/recursive_fib.lox",
-    closure: "tests/closure.lox",
-    print_clock: "tests/print_clock.lox",
-    scoping: "tests/scoping.lox",
-    class_creation: "tests/class_test.lox",
-    class_fields: "tests/class_fields.lox",
-    basic_methods: "tests/basic_methods.lox",
-    basic_init: "tests/basic_init.lox",
-    weird_init: "tests/weird_init.lox",
-    early_return: "tests/early_return.lox",
-    init_early_return: "tests/init_early_return.lox",
-    basic_inheritance: "tests/basic_inheritance.lox",
-    superclass_method: "tests/superclass_method.lox",
-    super_resolving: "tests/super_resolving.lox",
-);
\ No newline at end of file
@@ -0,0 +1,206 @@
+use std::{cell::RefCell, collections::HashMap, fmt::Display, rc::Rc};
+
+use crate::{callable::LoxCallable, error::{LoxError, LoxErrorKind}, gc::{HeapRef, Trace}, tree_walker::{TreeWalker, Value}};
+
+/// Storage behind a `Value::MapValue`. Like lists, maps are shared rather than copied.
+pub type MapRef = Rc<RefCell<LoxMap>>;
+
+/// The values that can be used as map keys.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum MapKey {
+    Number(u64), // bits of the f64, so the key can be hashed
+    String(String),
+    Boolean(bool),
+    Nil,
+}
+
+impl MapKey {
+    pub fn from_value(value: &Value) -> Result<MapKey, LoxError> {
+        match value {
+            Value::NumberValue(n) => {
+                if n.is_nan() {
+                    Err(LoxError::new(LoxErrorKind::TypeError, "NaN can't be used as a map key"))
+                } else if *n == 0.0 {
+                    // 0 and -0 are equal, so they need to be the same key
+                    Ok(MapKey::Number(0.0f64.to_bits()))
+                } else {
+                    Ok(MapKey::Number(n.to_bits()))
+                }
+            },
+            Value::StringValue(s) => Ok(MapKey::String(s.to_owned())),
+            Value::BooleanValue(b) => Ok(MapKey::Boolean(*b)),
+            Value::NilValue => Ok(MapKey::Nil),
+            other => Err(LoxError::new(LoxErrorKind::TypeError, format!("Map keys must be numbers, strings, booleans or nil, got {}", other.type_name())))
+        }
+    }
+
+    pub fn to_value(&self) -> Value {
+        match self {
+            MapKey::Number(bits) => Value::NumberValue(f64::from_bits(*bits)),
+            MapKey::String(s) => Value::StringValue(s.to_owned()),
+            MapKey::Boolean(b) => Value::BooleanValue(*b),
+            MapKey::Nil => Value::NilValue,
+        }
+    }
+}
+
+/// A hash map that iterates in insertion order.
+/// Re-assigning an existing key keeps its original position.
+#[derive(Debug, Clone, Default)]
+pub struct LoxMap {
+    entries: Vec<(MapKey, Value)>,
+    indices: HashMap<MapKey, usize>,
+}
+
+impl LoxMap {
+    pub fn new() -> LoxMap {
+        LoxMap { entries: Vec::new(), indices: HashMap::new() }
+    }
+
+    pub fn get(&self, key: &MapKey) -> Option<&Value> {
+        self.indices.get(key).map(|index| &self.entries[*index].1)
+    }
+
+    pub fn insert(&mut self, key: MapKey, value: Value) {
+        match self.indices.get(&key) {
+            Some(index) => self.entries[*index].1 = value,
+            None => {
+                self.indices.insert(key.clone(), self.entries.len());
+                self.entries.push((key, value));
+            }
+        }
+    }
+
+    pub fn remove(&mut self, key: &MapKey) -> Option<Value> {
+        let index = self.indices.remove(key)?;
+        let (_, value) = self.entries.remove(index);
+        // everything after the removed entry moved back one place
+        for (key, _) in &self.entries[index..] {
+            *self.indices.get_mut(key).unwrap() -= 1;
+        }
+        Some(value)
+    }
+
+    pub fn contains_key(&self, key: &MapKey) -> bool {
+        self.indices.contains_key(key)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &(MapKey, Value)> {
+        self.entries.iter()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.indices.clear();
+    }
+}
+
+impl Trace for LoxMap {
+    fn trace(&self, visit: &mut dyn FnMut(HeapRef)) {
+        for (_, value) in &self.entries {
+            value.trace(visit);
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum MapMethodKind {
+    Keys,
+    Values,
+    Has,
+    Remove,
+    Len,
+}
+
+/// A native method bound to a particular map, ex: the value of `map.keys`
+#[derive(Debug, Clone)]
+pub struct MapMethod {
+    map: MapRef,
+    kind: MapMethodKind,
+}
+
+/// Looks up a method on a map, as in `map.keys()`
+pub fn get_method(map: &MapRef, name: &str) -> Result<Value, LoxError> {
+    let kind = match name {
+        "keys" => MapMethodKind::Keys,
+        "values" => MapMethodKind::Values,
+        "has" => MapMethodKind::Has,
+        "remove" => MapMethodKind::Remove,
+        "len" => MapMethodKind::Len,
+        _ => return Err(LoxError::new(LoxErrorKind::AttributeError, format!("Map has no method '{}'", name)))
+    };
+    Ok(Value::Callable(Box::new(MapMethod { map: Rc::clone(map), kind })))
+}
+
+impl LoxCallable for MapMethod {
+    fn call(& self, interpreter: &mut TreeWalker, arguments: Vec<Value>) -> Result<Value, LoxError> {
+        match self.kind {
+            MapMethodKind::Keys => {
+                let keys = self.map.borrow().iter().map(|(key, _)| key.to_value()).collect();
+                Ok(Value::ListValue(interpreter.heap.list(keys)))
+            }
+            MapMethodKind::Values => {
+                let values = self.map.borrow().iter().map(|(_, value)| value.clone()).collect();
+                Ok(Value::ListValue(interpreter.heap.list(values)))
+            }
+            MapMethodKind::Has => {
+                let key = MapKey::from_value(&arguments[0])?;
+                Ok(Value::BooleanValue(self.map.borrow().contains_key(&key)))
+            }
+            MapMethodKind::Remove => {
+                let key = MapKey::from_value(&arguments[0])?;
+                match self.map.borrow_mut().remove(&key) {
+                    Some(value) => Ok(value),
+                    None => Err(LoxError::new(LoxErrorKind::KeyError, format!("Map has no entry with key {}", arguments[0].repr())))
+                }
+            }
+            MapMethodKind::Len => {
+                Ok(Value::NumberValue(self.map.borrow().len() as f64))
+            }
+        }
+    }
+
+    fn arity(&self) -> usize {
+        match self.kind {
+            MapMethodKind::Keys => 0,
+            MapMethodKind::Values => 0,
+            MapMethodKind::Has => 1,
+            MapMethodKind::Remove => 1,
+            MapMethodKind::Len => 0,
+        }
+    }
+
+    fn name(&self) -> String {
+        format!("map.{}", self.method_name())
+    }
+
+    fn trace(&self, visit: &mut dyn FnMut(HeapRef)) {
+        visit(HeapRef::Map(&self.map));
+    }
+}
+
+impl MapMethod {
+    fn method_name(&self) -> &'static str {
+        match self.kind {
+            MapMethodKind::Keys => "keys",
+            MapMethodKind::Values => "values",
+            MapMethodKind::Has => "has",
+            MapMethodKind::Remove => "remove",
+            MapMethodKind::Len => "len",
+        }
+    }
+}
+
+impl Display for MapMethod {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "<native fn map.{}>", self.method_name())
+    }
+}
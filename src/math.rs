@@ -0,0 +1,41 @@
+use crate::{error::{LoxError, LoxErrorKind}, native::NativeFunction, tree_walker::Value};
+
+/// The native math functions, ex: `sqrt`, to be defined as globals. All of them take and return numbers.
+pub fn natives() -> Vec<NativeFunction> {
+    vec![
+        unary("abs", f64::abs),
+        unary("sqrt", f64::sqrt),
+        unary("floor", f64::floor),
+        unary("ceil", f64::ceil),
+        unary("round", f64::round),
+        unary("sin", f64::sin),
+        unary("cos", f64::cos),
+        unary("tan", f64::tan),
+        unary("atan", f64::atan),
+        binary("atan2", f64::atan2),
+        unary("exp", f64::exp),
+        unary("log", f64::ln),
+        binary("pow", f64::powf),
+        binary("min", f64::min),
+        binary("max", f64::max),
+    ]
+}
+
+fn unary(name: &'static str, operation: fn(f64) -> f64) -> NativeFunction {
+    NativeFunction::new(name, 1, move |arguments| {
+        Ok(Value::NumberValue(operation(number(name, &arguments[0])?)))
+    })
+}
+
+fn binary(name: &'static str, operation: fn(f64, f64) -> f64) -> NativeFunction {
+    NativeFunction::new(name, 2, move |arguments| {
+        Ok(Value::NumberValue(operation(number(name, &arguments[0])?, number(name, &arguments[1])?)))
+    })
+}
+
+fn number(function: &str, argument: &Value) -> Result<f64, LoxError> {
+    match argument {
+        Value::NumberValue(n) => Ok(*n),
+        other => Err(LoxError::new(LoxErrorKind::TypeError, format!("Arguments to {} must be numbers, got {}", function, other.type_name())))
+    }
+}
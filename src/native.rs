@@ -1,26 +1,92 @@
-use std::{fmt::Display, time::SystemTime};
+use std::{fmt::{Debug, Display}, rc::Rc, time::SystemTime};
 
-use crate::{callable::LoxCallable, error::LoxError, error::LoxErrorKind::RuntimeError, tree_walker::Value};
+use crate::{callable::LoxCallable, error::LoxError, error::LoxErrorKind::RuntimeError, tree_walker::{TreeWalker, Value}};
 
 
 #[derive(Debug, Clone)]
-pub(crate) struct ClockCallable {}
+pub struct ClockCallable {}
 
 impl LoxCallable for ClockCallable {
     fn call(& self, _interpreter:  &mut crate::tree_walker::TreeWalker, _arguments: Vec<crate::tree_walker::Value>) -> Result<crate::tree_walker::Value, crate::error::LoxError> {
         match SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
             Ok(n) => { Ok(Value::NumberValue(n.as_secs() as f64)) }
-            Err(_) => { Err(LoxError {kind: RuntimeError, message: "System time before unix epoch" })}
+            Err(_) => { Err(LoxError::new(RuntimeError, "System time before unix epoch"))}
         }
     }
 
     fn arity(&self) -> usize {
         0
     }
+
+    fn name(&self) -> String {
+        String::from("clock")
+    }
 }
 
 impl Display for ClockCallable {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "<native fn clock>")
     }
-}
\ No newline at end of file
+}
+
+type NativeFn = dyn Fn(&mut TreeWalker, Vec<Value>) -> Result<Value, LoxError>;
+
+/// A native function backed by a Rust closure, so host applications
+/// can expose functions to scripts without writing a `LoxCallable` for each one.
+#[derive(Clone)]
+pub struct NativeFunction {
+    name: String,
+    arity: usize,
+    min_arity: usize,
+    function: Rc<NativeFn>,
+}
+
+impl NativeFunction {
+    pub fn new<F>(name: &str, arity: usize, function: F) -> NativeFunction
+    where F: Fn(Vec<Value>) -> Result<Value, LoxError> + 'static {
+        NativeFunction::with_runtime(name, arity, move |_, arguments| function(arguments))
+    }
+
+    /// Like `new`, for the interpreter's own natives that need its runtime, ex: to allocate a list.
+    pub(crate) fn with_runtime<F>(name: &str, arity: usize, function: F) -> NativeFunction
+    where F: Fn(&mut TreeWalker, Vec<Value>) -> Result<Value, LoxError> + 'static {
+        NativeFunction { name: name.to_owned(), arity, min_arity: arity, function: Rc::new(function) }
+    }
+
+    /// Lets scripts leave out the arguments after the first `min_arity`,
+    /// so the closure gets anywhere from `min_arity` to `arity` of them.
+    pub fn with_min_arity(mut self, min_arity: usize) -> NativeFunction {
+        self.min_arity = min_arity;
+        self
+    }
+}
+
+impl LoxCallable for NativeFunction {
+    fn call(& self, interpreter: &mut TreeWalker, arguments: Vec<Value>) -> Result<Value, LoxError> {
+        (self.function)(interpreter, arguments)
+    }
+
+    fn arity(&self) -> usize {
+        self.arity
+    }
+
+    fn min_arity(&self) -> usize {
+        self.min_arity
+    }
+
+    fn name(&self) -> String {
+        self.name.to_owned()
+    }
+}
+
+impl Debug for NativeFunction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("NativeFunction").field("name", &self.name).field("arity", &self.arity).finish()
+    }
+}
+
+impl Display for NativeFunction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "<native fn {}>", self.name)
+    }
+}
@@ -1,37 +1,109 @@
-use crate::tree_walker::Value;
+use std::{cell::RefCell, fmt::Debug, io::Write, rc::Rc};
 
+use crate::{error::{LoxError, LoxErrorKind}, tree_walker::Value};
 
-#[derive(Debug)]
-pub(crate) struct Printer {
+/// Destination for the values printed by Lox `print` statements.
+pub trait OutputSink: Debug {
+    fn output_value(&mut self, value: &Value) -> Result<(), LoxError>;
+
+    /// Shows the prompt passed to `input(prompt)`. Sinks that can leave the line open for
+    /// the user's answer should, by default it's output like a printed string.
+    fn output_prompt(&mut self, prompt: &str) -> Result<(), LoxError> {
+        self.output_value(&Value::StringValue(prompt.to_owned()))
+    }
+}
+
+/// Prints values to stdout, one per line.
+#[derive(Debug, Default)]
+pub struct Printer {
 
 }
 
-// We're allowing dead code, so that warnings aren't generated
-// however the code isn't actually dead. I guess rustc/rust-analyzer can't tell that
-// because we're using conditional compilation. (see tree_walker.rs)
-#[allow(dead_code)]
 impl Printer {
     pub fn new() -> Printer {
         Printer{}
     }
+}
 
-    pub fn output_value(&mut self, value: Value) {
+impl OutputSink for Printer {
+    fn output_value(&mut self, value: &Value) -> Result<(), LoxError> {
         println!("{}", value);
+        Ok(())
     }
-}
 
+    fn output_prompt(&mut self, prompt: &str) -> Result<(), LoxError> {
+        print!("{}", prompt);
+        match std::io::stdout().flush() {
+            Ok(_) => Ok(()),
+            Err(_) => Err(LoxError::new(LoxErrorKind::RuntimeError, "Failed to write output"))
+        }
+    }
+}
 
-#[derive(Debug)]
-pub(crate) struct Recorder {
-    pub outputted: Vec<String>
+/// Records printed values in memory.
+///
+/// Clones share the same buffer, so keep a clone around to read
+/// what was printed after handing the recorder to an interpreter.
+#[derive(Debug, Clone, Default)]
+pub struct Recorder {
+    outputted: Rc<RefCell<Vec<String>>>
 }
 
-#[allow(dead_code)]
 impl Recorder {
     pub fn new() -> Recorder {
-        Recorder {outputted: Vec::new()}
+        Recorder {outputted: Rc::new(RefCell::new(Vec::new()))}
+    }
+
+    /// Every value printed so far, formatted with `Value`'s `Display` impl.
+    pub fn outputted(&self) -> Vec<String> {
+        self.outputted.borrow().clone()
+    }
+
+    pub fn clear(&self) {
+        self.outputted.borrow_mut().clear();
+    }
+}
+
+impl OutputSink for Recorder {
+    fn output_value(&mut self, value: &Value) -> Result<(), LoxError> {
+        self.outputted.borrow_mut().push(format!("{}", value));
+        Ok(())
     }
-    pub fn output_value(&mut self, value: Value) {
-        self.outputted.push(format!("{}", value));
+}
+
+/// Writes values, one per line, to any `io::Write` such as a file, socket or log buffer.
+pub struct WriteSink<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> WriteSink<W> {
+    pub fn new(writer: W) -> WriteSink<W> {
+        WriteSink { writer }
     }
-}
\ No newline at end of file
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+impl<W: Write> Debug for WriteSink<W> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "WriteSink")
+    }
+}
+
+impl<W: Write> OutputSink for WriteSink<W> {
+    fn output_value(&mut self, value: &Value) -> Result<(), LoxError> {
+        match writeln!(self.writer, "{}", value) {
+            Ok(_) => Ok(()),
+            Err(_) => Err(LoxError::new(LoxErrorKind::RuntimeError, "Failed to write output"))
+        }
+    }
+
+    fn output_prompt(&mut self, prompt: &str) -> Result<(), LoxError> {
+        match write!(self.writer, "{}", prompt).and_then(|_| self.writer.flush()) {
+            Ok(_) => Ok(()),
+            Err(_) => Err(LoxError::new(LoxErrorKind::RuntimeError, "Failed to write output"))
+        }
+    }
+}
@@ -1,12 +1,12 @@
-use std::{iter::Peekable, slice::Iter};
+use std::{iter::Peekable, rc::Rc, slice::Iter};
 
-use crate::{ast::{Assignment, Binary, BlockStatement, Call, ClassDeclStatement, Expr, ExpressionStatement, FunDeclStatement, Get, Grouping, IfStatement, Literal, Logical, LogicalOperator, PrintStatement, ReturnStatement, Set, Statement, Super, This, Unary, UnaryOperator, VarDeclStatement, Variable, WhileStatement}, error::{LoxError, LoxErrorKind}, tokens::{LiteralValue, Token, TokenType}};
+use crate::{ast::{Assignment, Binary, BlockStatement, BreakStatement, Call, CatchClause, ClassDeclStatement, ContinueStatement, Expr, ExpressionStatement, FunDeclStatement, Get, Grouping, IfStatement, Index, IndexSet, Interpolation, Lambda, List, Literal, Logical, Map, LogicalOperator, PrintStatement, ReturnStatement, Set, Statement, Super, This, ThrowStatement, TryStatement, Unary, UnaryOperator, VarDeclStatement, Variable, WhileStatement}, error::{LoxError, LoxErrorKind}, tokens::{LiteralValue, Span, Token, TokenType}};
 use crate::ast::{BinaryOperator};
 
 
 const MAX_PARAMETERS: usize = 255;
 
-pub(crate) struct Parser {
+pub struct Parser {
     pub errors: Vec<LoxError>,
 }
 
@@ -15,6 +15,51 @@ enum FunctionKind {
     Method,
 }
 
+// whether the tokens start with "fun" "(", rather than a named function declaration
+fn is_lambda(tokens: &Peekable<Iter<Token>>) -> bool {
+    let mut lookahead = tokens.clone();
+    lookahead.next(); // skip "fun"
+    matches!(lookahead.peek().map(|token| &token.token_type), Some(TokenType::LeftParen))
+}
+
+// whether the tokens start with the parameter list of an arrow function, ex: "(a, b) =>"
+fn is_arrow_function(tokens: &Peekable<Iter<Token>>) -> bool {
+    let mut lookahead = tokens.clone();
+    lookahead.next(); // skip "("
+    loop {
+        match lookahead.next().map(|token| &token.token_type) {
+            Some(TokenType::Identifier) | Some(TokenType::Comma) => {},
+            Some(TokenType::RightParen) => break,
+            _ => return false,
+        }
+    }
+    matches!(lookahead.peek().map(|token| &token.token_type), Some(TokenType::Arrow))
+}
+
+// the name given to functions that don't have one, so they print as "<fn anonymous>"
+fn anonymous(token: &Token) -> Token {
+    Token { token_type: TokenType::Identifier, lexeme: String::from("anonymous"), literal: None, span: token.span, id: token.id }
+}
+
+// describes the token a syntax error was found at, ex: "found 'foo'"
+fn found(token: &Token) -> String {
+    match token.token_type {
+        TokenType::EOF => String::from("found end of file"),
+        _ => format!("found '{}'", token.lexeme),
+    }
+}
+
+// a syntax error at `token` for when the parser wanted `what` instead, ex: "expected ';' after value, found '}'"
+fn expected(what: &str, token: &Token) -> LoxError {
+    LoxError::new(LoxErrorKind::SyntaxError, format!("expected {}, {}", what, found(token))).with_span(token.span)
+}
+
+impl Default for Parser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Parser {
     
 
@@ -23,84 +68,88 @@ impl Parser {
     }
 
     // program -> statement* EOF ;
-    pub fn parse(&mut self, tokens: & Vec<Token>) -> Result<Vec<Statement>, ()> {
+    #[allow(clippy::result_unit_err)]
+    pub fn parse(&mut self, tokens: &[Token]) -> Result<Vec<Statement>, ()> {
         let mut tokens = tokens.iter().peekable();
         let mut statements: Vec<Statement> = Vec::new();
         
-        loop {
-            match tokens.peek() {
-                Some(token) => {
-                    match token.token_type {
-                        TokenType::EOF => {
-                            break
+        while let Some(token) = tokens.peek() {
+            match token.token_type {
+                TokenType::EOF => {
+                    break
+                },
+                _ => {
+                    let result = self.declaration(&mut tokens);
+                    match result {
+                        Ok(s) => {
+                            statements.push(s)
                         },
-                        _ => {
-                            let result = self.declaration(&mut tokens);
-                            match result {
-                                Ok(s) => {
-                                    statements.push(s)
-                                },
-                                Err(e) => {
-                                    self.errors.push(e);
-                                    self.synchronize(&mut tokens);
-                                }
-                            }
+                        Err(e) => {
+                            self.errors.push(e);
+                            self.synchronize(&mut tokens);
                         }
                     }
                 }
-                None => {
-                    break;
-                }
             }
-            
         }
 
-        if self.errors.len() > 0 {
+        if !self.errors.is_empty() {
             Err(())
         } else {
             Ok(statements)
         }
     }
 
+    // expression EOF ;
+    #[allow(clippy::result_unit_err)]
+    pub fn parse_expression(&mut self, tokens: &[Token]) -> Result<Expr, ()> {
+        let mut tokens = tokens.iter().peekable();
+        let result = self.expression(&mut tokens).and_then(|expr| {
+            match &tokens.peek().unwrap().token_type {
+                TokenType::EOF => Ok(expr),
+                _ => Err(expected("end of expression", tokens.peek().unwrap()))
+            }
+        });
+        match result {
+            Ok(expr) => Ok(expr),
+            Err(e) => {
+                self.errors.push(e);
+                Err(())
+            }
+        }
+    }
+
     fn synchronize(&mut self, tokens: &mut Peekable<Iter<Token>>) {
         let mut next = tokens.next();
 
-        loop {
-            match next {
-                Some(token) => {
-                    // if we just consumed a semicolon,
-                    // we're synchronized and ready to parse the next statement
-                    match token.token_type {
-                        TokenType::Semicolon => break,
+        while let Some(token) = next {
+            // if we just consumed a semicolon,
+            // we're synchronized and ready to parse the next statement
+            if let TokenType::Semicolon = token.token_type { break };
+
+            match tokens.peek() {
+                // if the next token in the list is one of the below
+                // we are ready to start parsing the next statement,
+                // since these token types all are used to start statements
+                Some(peeked) => {
+                    match peeked.token_type {
+                        TokenType::Class => break,
+                        TokenType::Fun => break,
+                        TokenType::Var => break,
+                        TokenType::For => break,
+                        TokenType::If => break,
+                        TokenType::While => break,
+                        TokenType::Print => break,
+                        TokenType::Return => break,
+                        TokenType::Try => break,
+                        TokenType::Throw => break,
                         _ => {}
-                    };
-        
-                    match tokens.peek() {
-                        // if the next token in the list is one of the below
-                        // we are ready to start parsing the next statement,
-                        // since these token types all are used to start statements
-                        Some(peeked) => {
-                            match peeked.token_type {
-                                TokenType::Class => break,
-                                TokenType::Fun => break,
-                                TokenType::Var => break,
-                                TokenType::For => break,
-                                TokenType::If => break,
-                                TokenType::While => break,
-                                TokenType::Print => break,
-                                TokenType::Return => break,
-                                _ => {}
-                            }
-                        },
-                        None => break
-                    };
-        
-                    next = tokens.next();
+                    }
                 },
-                None => {
-                    break;
-                }
-            }
+                None => break
+            };
+
+            next = tokens.next();
         }
     }
 
@@ -113,7 +162,8 @@ impl Parser {
             TokenType::Var => {
                 self.var_declaration(tokens)
             },
-            TokenType::Fun => {
+            // "fun (" starts an anonymous function, which is an expression
+            TokenType::Fun if !is_lambda(tokens) => {
                 self.fun_declaration(tokens)
             }
             _ => self.statement(tokens)
@@ -129,7 +179,7 @@ impl Parser {
         match &tokens.peek().unwrap().token_type {
             TokenType::Identifier => name = tokens.next().unwrap().to_owned(),
             _ => {
-                return Err(LoxError {kind: LoxErrorKind::SyntaxError(tokens.peek().unwrap().line), message: "Expected class name"})
+                return Err(expected("class name", tokens.peek().unwrap()))
             }
         };
         match &tokens.peek().unwrap().token_type {
@@ -140,7 +190,7 @@ impl Parser {
                         superclass = Some(Variable{ token: tokens.next().unwrap().to_owned() });
                     },
                     _ => {
-                        return Err(LoxError {kind: LoxErrorKind::SyntaxError(tokens.peek().unwrap().line), message: "Expected super class name"})
+                        return Err(expected("superclass name", tokens.peek().unwrap()))
                     }
                 }
             },
@@ -151,7 +201,7 @@ impl Parser {
         match &tokens.peek().unwrap().token_type {
             TokenType::LeftBrace => tokens.next(), // consume '{'
             _ => {
-                return Err(LoxError {kind: LoxErrorKind::SyntaxError(tokens.peek().unwrap().line), message: "Expected { after class declaration"})
+                return Err(expected("'{' before class body", tokens.peek().unwrap()))
             }
         };
         let mut methods = Vec::new();
@@ -162,7 +212,7 @@ impl Parser {
                     break;
                 },
                 TokenType::EOF => {
-                    return Err(LoxError {kind: LoxErrorKind::SyntaxError(tokens.peek().unwrap().line), message: "reached EOF while parsing, expected '}'"})
+                    return Err(LoxError::new(LoxErrorKind::SyntaxError, "reached EOF while parsing, expected '}'").with_span(tokens.peek().unwrap().span))
                 }
                 _ => {}
             };
@@ -178,64 +228,22 @@ impl Parser {
     }
 
     // function -> IDENTIFIER "(" parameters? ")" blockStatement ;
-    fn function(&mut self, tokens: &mut Peekable<Iter<Token>>, kind: FunctionKind) -> Result<FunDeclStatement, LoxError> {
-        let name;
-        match &tokens.peek().unwrap().token_type {
-            TokenType::Identifier => name = tokens.next().unwrap().to_owned(),
+    fn function(&mut self, tokens: &mut Peekable<Iter<Token>>, kind: FunctionKind) -> Result<Rc<FunDeclStatement>, LoxError> {
+        
+        let name = match &tokens.peek().unwrap().token_type {
+            TokenType::Identifier => tokens.next().unwrap().to_owned(),
             _ => {
-                let message = match kind {
-                    FunctionKind::Function => { "expected function name"}
-                    FunctionKind::Method => { "expected method name" }
+                let what = match kind {
+                    FunctionKind::Function => { "function name"}
+                    FunctionKind::Method => { "method name" }
                 };
-                return Err(LoxError {kind: LoxErrorKind::SyntaxError(tokens.peek().unwrap().line), message});
+                return Err(expected(what, tokens.peek().unwrap()));
             }
         };
 
         match &tokens.peek().unwrap().token_type {
             TokenType::LeftParen => {
-                tokens.next(); // consume "("
-                let mut parameters = Vec::new();
-                match &tokens.peek().unwrap().token_type {
-                    TokenType::RightParen => {
-                        // no parameters
-                    },
-                    _ => {
-                        loop {
-                            if parameters.len() > MAX_PARAMETERS {
-                                // no need to return the Error
-                                // that would mean the parser is in a bad state and needs to synchronize
-                                // but we don't need to do that for this type of error
-                                self.errors.push(LoxError {kind: LoxErrorKind::SyntaxError(tokens.peek().unwrap().line), message: "can't have > 255 arguments to a function call"})
-                            }
-                            match &tokens.peek().unwrap().token_type {
-                                TokenType::Identifier => {
-                                    parameters.push(tokens.next().unwrap().to_owned());
-                                },
-                                _ => {
-                                    return Err(LoxError {kind: LoxErrorKind::SyntaxError(tokens.peek().unwrap().line), message: "expected identifier"});
-                                }
-                            }
-
-                            match &tokens.peek().unwrap().token_type {
-                                TokenType::Comma => {
-                                    tokens.next(); // consume ','
-                                },
-                                _ => {
-                                    break;
-                                }
-                            }
-                        }
-                    }
-                }
-
-                match &tokens.peek().unwrap().token_type {
-                    TokenType::RightParen => {
-                        tokens.next(); // consume ")"
-                    },
-                    _ => {
-                        return Err(LoxError {kind: LoxErrorKind::SyntaxError(tokens.peek().unwrap().line), message: "expected ')' after parameters"})
-                    }
-                }
+                let parameters = self.parameters(tokens)?;
 
                 let body;
                 match &tokens.peek().unwrap().token_type {
@@ -243,25 +251,73 @@ impl Parser {
                         body = self.block(tokens)?;
                     },
                     _ => {
-                        let message = match kind {
-                            FunctionKind::Function => { "expected '{' afer function body"}
-                            FunctionKind::Method => { "expected '{' after method body" }
+                        let what = match kind {
+                            FunctionKind::Function => { "'{' before function body"}
+                            FunctionKind::Method => { "'{' before method body" }
                         };
-                        return Err(LoxError {kind: LoxErrorKind::SyntaxError(tokens.peek().unwrap().line), message})
+                        return Err(expected(what, tokens.peek().unwrap()))
                     }
                 };
 
 
-                Ok(FunDeclStatement {name, body, parameters})
+                Ok(Rc::new(FunDeclStatement {name, body, parameters}))
             },
             _ => {
-                let message = match kind {
-                    FunctionKind::Function => { "expected '(' afer function name"}
-                    FunctionKind::Method => { "expected '(' after method name" }
+                let what = match kind {
+                    FunctionKind::Function => { "'(' after function name"}
+                    FunctionKind::Method => { "'(' after method name" }
                 };
-                Err(LoxError {kind: LoxErrorKind::SyntaxError(tokens.peek().unwrap().line), message})
+                Err(expected(what, tokens.peek().unwrap()))
+            }
+        }
+    }
+
+    // parameters -> "(" ( IDENTIFIER ( "," IDENTIFIER )* )? ")" ;
+    fn parameters(&mut self, tokens: &mut Peekable<Iter<Token>>) -> Result<Vec<Token>, LoxError> {
+        tokens.next(); // consume "("
+        let mut parameters = Vec::new();
+        match &tokens.peek().unwrap().token_type {
+            TokenType::RightParen => {
+                // no parameters
+            },
+            _ => {
+                loop {
+                    if parameters.len() > MAX_PARAMETERS {
+                        // no need to return the Error
+                        // that would mean the parser is in a bad state and needs to synchronize
+                        // but we don't need to do that for this type of error
+                        self.errors.push(LoxError::new(LoxErrorKind::SyntaxError, "can't have > 255 arguments to a function call").with_span(tokens.peek().unwrap().span))
+                    }
+                    match &tokens.peek().unwrap().token_type {
+                        TokenType::Identifier => {
+                            parameters.push(tokens.next().unwrap().to_owned());
+                        },
+                        _ => {
+                            return Err(expected("identifier", tokens.peek().unwrap()));
+                        }
+                    }
+
+                    match &tokens.peek().unwrap().token_type {
+                        TokenType::Comma => {
+                            tokens.next(); // consume ','
+                        },
+                        _ => {
+                            break;
+                        }
+                    }
+                }
             }
         }
+
+        match &tokens.peek().unwrap().token_type {
+            TokenType::RightParen => {
+                tokens.next(); // consume ")"
+            },
+            _ => {
+                return Err(expected("')' after parameters", tokens.peek().unwrap()))
+            }
+        }
+        Ok(parameters)
     }
 
     fn var_declaration(&mut self, tokens: &mut Peekable<Iter<Token>>) -> Result<Statement, LoxError> {
@@ -270,18 +326,17 @@ impl Parser {
         match &tokens.peek().unwrap().token_type {
             TokenType::Identifier => token = tokens.next().unwrap().to_owned(),
             _ => {
-                return Err(LoxError {kind: LoxErrorKind::SyntaxError(tokens.peek().unwrap().line), message: "expected identifier"})
+                return Err(expected("identifier", tokens.peek().unwrap()))
             }
         };
 
-        let initializer;
-        match &tokens.peek().unwrap().token_type {
+        let initializer = match &tokens.peek().unwrap().token_type {
             TokenType::Equal => {
                 tokens.next(); // consume '='
-                initializer = Some(self.expression(tokens)?);
+                Some(self.expression(tokens)?)
             },
             _ => {
-                initializer = None;
+                None
             }
         };
 
@@ -290,7 +345,7 @@ impl Parser {
                 tokens.next(); // consume ";"
             },
             _ => {
-              return Err(LoxError {kind: LoxErrorKind::SyntaxError(tokens.peek().unwrap().line), message: "expected ';' variable declaration"})  
+              return Err(expected("';' after variable declaration", tokens.peek().unwrap()))  
             }
         };
         Ok(Statement::VarDeclStatement(VarDeclStatement {token, initializer}))
@@ -302,7 +357,11 @@ impl Parser {
     // | ifStatement
     // | whileStatement 
     // | forStatement
-    // | returnStatement ;
+    // | returnStatement
+    // | breakStatement
+    // | continueStatement
+    // | tryStatement
+    // | throwStatement ;
     fn statement(&mut self, tokens: &mut Peekable<Iter<Token>>) -> Result<Statement, LoxError> {
         match &tokens.peek().unwrap().token_type {
             TokenType::Print => {
@@ -323,6 +382,15 @@ impl Parser {
             TokenType::Return => {
                 self.return_statement(tokens)
             }
+            TokenType::Break | TokenType::Continue => {
+                self.loop_control_statement(tokens)
+            }
+            TokenType::Try => {
+                self.try_statement(tokens)
+            }
+            TokenType::Throw => {
+                self.throw_statement(tokens)
+            }
             _ => {
                 // if the next token doesn't like any other statement, assume its an expr statement
                 self.expression_statement(tokens)
@@ -339,7 +407,7 @@ impl Parser {
                 tokens.next(); // consume ";"
             },
             _ => {
-              return Err(LoxError {kind: LoxErrorKind::SyntaxError(tokens.peek().unwrap().line), message: "expected ';' after statement"})  
+              return Err(expected("';' after statement", tokens.peek().unwrap()))  
             }
         };
         Ok(Statement::PrintStatement(PrintStatement {token, value}))
@@ -362,7 +430,7 @@ impl Parser {
                     break;
                 },
                 TokenType::EOF => {
-                    return Err(LoxError {kind: LoxErrorKind::SyntaxError(tokens.peek().unwrap().line), message: "reached EOF while parsing, expected '}'"})
+                    return Err(LoxError::new(LoxErrorKind::SyntaxError, "reached EOF while parsing, expected '}'").with_span(tokens.peek().unwrap().span))
                 }
                 _ => {
                     statements.push(self.declaration(tokens)?);
@@ -381,7 +449,7 @@ impl Parser {
                 tokens.next(); // consume "("
             },
             _ => {
-                return Err(LoxError {kind: LoxErrorKind::SyntaxError(tokens.peek().unwrap().line), message: "expected '(' after if"})
+                return Err(expected("'(' after if", tokens.peek().unwrap()))
             }
         };
 
@@ -392,7 +460,7 @@ impl Parser {
                 tokens.next(); // consume ")"
             },
             _ => {
-                return Err(LoxError {kind: LoxErrorKind::SyntaxError(tokens.peek().unwrap().line), message: "expected ')' after if condition"})
+                return Err(expected("')' after if condition", tokens.peek().unwrap()))
             }
         };
 
@@ -420,7 +488,7 @@ impl Parser {
                 tokens.next(); // consume "("
             },
             _ => {
-                return Err(LoxError {kind: LoxErrorKind::SyntaxError(tokens.peek().unwrap().line), message: "expected '(' after while"})
+                return Err(expected("'(' after while", tokens.peek().unwrap()))
             }
         };
 
@@ -431,12 +499,12 @@ impl Parser {
                 tokens.next(); // consume ")"
             },
             _ => {
-                return Err(LoxError {kind: LoxErrorKind::SyntaxError(tokens.peek().unwrap().line), message: "expected ')' after while condition"})
+                return Err(expected("')' after while condition", tokens.peek().unwrap()))
             }
         };
 
         let body = Box::new(self.statement(tokens)?);
-        Ok(Statement::WhileStatement(WhileStatement {condition, body}))
+        Ok(Statement::WhileStatement(WhileStatement {condition, body, increment: None}))
     }
 
     // forStatement -> "for" "(" (varDecl | exprStatement | ";") expression? ";" expression? ")" statement ; 
@@ -448,35 +516,31 @@ impl Parser {
                 tokens.next(); // consume "("
             },
             _ => {
-                return Err(LoxError {kind: LoxErrorKind::SyntaxError(tokens.peek().unwrap().line), message: "expected '(' after for"})
+                return Err(expected("'(' after for", tokens.peek().unwrap()))
             }
         };
         
-        let initializer;
-
-        match tokens.peek().unwrap().token_type {
+        let initializer = match tokens.peek().unwrap().token_type {
             TokenType::Semicolon => {
-                initializer = None;
+                None
             }
             TokenType::Var => {
-                initializer = Some(self.var_declaration(tokens)?);
+                Some(self.var_declaration(tokens)?)
             },
             _ => {
-                initializer = Some(self.expression_statement(tokens)?);
+                Some(self.expression_statement(tokens)?)
             }
         };
 
-        let condition;
-
-        match tokens.peek().unwrap().token_type {
+        let condition = match tokens.peek().unwrap().token_type {
             TokenType::Semicolon => {
-                condition = Expr::Literal(Literal {
-                    value: LiteralValue::BooleanValue(true), 
+                Expr::Literal(Literal {
+                    value: LiteralValue::BooleanValue(true),
                     token: tokens.peek().unwrap().to_owned().to_owned() // yeah it gets the ";" token idk
-                });
+                })
             }
             _ => {
-                condition = self.expression(tokens)?;
+                self.expression(tokens)?
             }
         };
 
@@ -485,18 +549,16 @@ impl Parser {
                 tokens.next(); // consume ";"
             },
             _ => {
-                return Err(LoxError {kind: LoxErrorKind::SyntaxError(tokens.peek().unwrap().line), message: "expected ';' after for condition"})
+                return Err(expected("';' after for condition", tokens.peek().unwrap()))
             }
         };
 
-        let increment;
-
-        match tokens.peek().unwrap().token_type {
+        let increment = match tokens.peek().unwrap().token_type {
             TokenType::Semicolon => {
-                increment = None;
+                None
             }
             _ => {
-                increment = Some(self.expression(tokens)?);
+                Some(self.expression(tokens)?)
             }
         };
 
@@ -505,7 +567,7 @@ impl Parser {
                 tokens.next(); // consume ")"
             },
             _ => {
-                return Err(LoxError {kind: LoxErrorKind::SyntaxError(tokens.peek().unwrap().line), message: "expected ')' after for clause"})
+                return Err(expected("')' after for clause", tokens.peek().unwrap()))
             }
         };
 
@@ -513,30 +575,14 @@ impl Parser {
 
         // finished parsing, time to desugar
 
-        let while_node = match increment {
-            Some(increment) => {
-                // if increment exists,
-                // then create:
-                /* 
-                    while (condition) {
-                        <body>
-                        <increment>
-                    }
-                */
-                let increment_statement = Statement::ExpressionStatement(ExpressionStatement {expression: increment});
-                let block = Statement::BlockStatement(BlockStatement {statements: vec![body, increment_statement]});
-                 Statement::WhileStatement(WhileStatement {condition, body: Box::new(block) }) 
-            },
-            None => {
-                // if increment is none,
-                // then create:
-                /* 
-                    while (condition)
-                        <body>
-                */
-                Statement::WhileStatement(WhileStatement {condition, body: Box::new(body)})
-            }
-        };
+        // create:
+        /*
+            while (condition)
+                <body>
+        */
+        // the increment (if any) is stored on the while node instead of being appended to the body,
+        // so that it still runs after a "continue" skips the rest of the body
+        let while_node = Statement::WhileStatement(WhileStatement {condition, body: Box::new(body), increment});
 
         match initializer {
             // if initializer exists
@@ -558,6 +604,24 @@ impl Parser {
         }
     }
 
+    // breakStatement -> "break" ";" ;
+    // continueStatement -> "continue" ";" ;
+    fn loop_control_statement(&mut self, tokens: &mut Peekable<Iter<Token>>) -> Result<Statement, LoxError> {
+        let keyword = tokens.next().unwrap().to_owned(); // consume "break" or "continue"
+        match &tokens.peek().unwrap().token_type {
+            TokenType::Semicolon => {
+                tokens.next(); // consume ";"
+            },
+            _ => {
+                return Err(expected("';' after loop control statement", tokens.peek().unwrap()))
+            }
+        };
+        match keyword.token_type {
+            TokenType::Break => Ok(Statement::BreakStatement(BreakStatement {keyword})),
+            _ => Ok(Statement::ContinueStatement(ContinueStatement {keyword})),
+        }
+    }
+
     // returnStatement -> "return" expression? ";" ;
     fn return_statement(&mut self, tokens: &mut Peekable<Iter<Token>>) -> Result<Statement, LoxError> {
         let keyword = tokens.next().unwrap().to_owned(); // consume "return"
@@ -574,7 +638,7 @@ impl Parser {
                         tokens.next(); // consume ";"
                     },
                     _ => {
-                        return Err(LoxError {kind: LoxErrorKind::SyntaxError(tokens.peek().unwrap().line), message: "expected ';' after return statement"})  
+                        return Err(expected("';' after return statement", tokens.peek().unwrap()))  
                     }
                 }
             }
@@ -582,6 +646,80 @@ impl Parser {
         Ok(Statement::ReturnStatement(ReturnStatement {keyword, value}))
     }
 
+    // tryStatement -> "try" block ( "catch" "(" IDENTIFIER ")" block )? ( "finally" block )? ;
+    // at least one of the catch and finally clauses is required
+    fn try_statement(&mut self, tokens: &mut Peekable<Iter<Token>>) -> Result<Statement, LoxError> {
+        let keyword = tokens.next().unwrap().to_owned(); // consume "try"
+        let body = self.required_block(tokens, "'{' after try")?;
+
+        let catch = match &tokens.peek().unwrap().token_type {
+            TokenType::Catch => {
+                tokens.next(); // consume "catch"
+                match &tokens.peek().unwrap().token_type {
+                    TokenType::LeftParen => {
+                        tokens.next(); // consume "("
+                    },
+                    _ => {
+                        return Err(expected("'(' after catch", tokens.peek().unwrap()))
+                    }
+                };
+                let name = match &tokens.peek().unwrap().token_type {
+                    TokenType::Identifier => tokens.next().unwrap().to_owned(),
+                    _ => {
+                        return Err(expected("exception variable name", tokens.peek().unwrap()))
+                    }
+                };
+                match &tokens.peek().unwrap().token_type {
+                    TokenType::RightParen => {
+                        tokens.next(); // consume ")"
+                    },
+                    _ => {
+                        return Err(expected("')' after exception variable", tokens.peek().unwrap()))
+                    }
+                };
+                let body = self.required_block(tokens, "'{' after catch clause")?;
+                Some(CatchClause {name, body})
+            },
+            _ => None
+        };
+
+        let finally = match &tokens.peek().unwrap().token_type {
+            TokenType::Finally => {
+                tokens.next(); // consume "finally"
+                Some(self.required_block(tokens, "'{' after finally")?)
+            },
+            _ => None
+        };
+
+        if catch.is_none() && finally.is_none() {
+            return Err(expected("catch or finally after try block", tokens.peek().unwrap()))
+        }
+        Ok(Statement::TryStatement(TryStatement {keyword, body, catch, finally}))
+    }
+
+    // like block, but reports that `what` was expected if the next token doesn't start one
+    fn required_block(&mut self, tokens: &mut Peekable<Iter<Token>>, what: &str) -> Result<Vec<Statement>, LoxError> {
+        match &tokens.peek().unwrap().token_type {
+            TokenType::LeftBrace => self.block(tokens),
+            _ => Err(expected(what, tokens.peek().unwrap()))
+        }
+    }
+
+    // throwStatement -> "throw" expression ";" ;
+    fn throw_statement(&mut self, tokens: &mut Peekable<Iter<Token>>) -> Result<Statement, LoxError> {
+        let keyword = tokens.next().unwrap().to_owned(); // consume "throw"
+        let value = self.expression(tokens)?;
+        match &tokens.peek().unwrap().token_type {
+            TokenType::Semicolon => {
+                tokens.next(); // consume ";"
+            },
+            _ => {
+                return Err(expected("';' after throw statement", tokens.peek().unwrap()))
+            }
+        };
+        Ok(Statement::ThrowStatement(ThrowStatement {keyword, value}))
+    }
+
     // exprStatement -> expression ";" ;
     fn expression_statement(&mut self, tokens: &mut Peekable<Iter<Token>>) -> Result<Statement, LoxError> {
         let expr = self.expression(tokens)?;
@@ -590,7 +728,7 @@ impl Parser {
                 tokens.next(); // consume ";"
             },
             _ => {
-              return Err(LoxError {kind: LoxErrorKind::SyntaxError(tokens.peek().unwrap().line), message: "expected ';' after statement"})  
+              return Err(expected("';' after statement", tokens.peek().unwrap()))  
             }
         };
         Ok(Statement::ExpressionStatement(ExpressionStatement {expression: expr}))
@@ -601,7 +739,7 @@ impl Parser {
         self.assignment(tokens)
     }
 
-    // assignment -> (call ".")? IDENTIFIER "=" assignment | logic_or ;
+    // assignment -> (call ".")? IDENTIFIER "=" assignment | call "[" expression "]" "=" assignment | logic_or ;
     fn assignment(&mut self, tokens: &mut Peekable<Iter<Token>>) -> Result<Expr, LoxError> {
         let expr = self.or(tokens)?;
 
@@ -609,6 +747,7 @@ impl Parser {
             TokenType::Equal => {
                 tokens.next().unwrap(); // consume "="
                 let value = self.assignment(tokens)?;
+                let target_span = expr.span();
                 match expr {
                     Expr::Variable(v) => {
                         return Ok(Expr::Assignment(Assignment {token: v.token, value: Box::new(value)}));
@@ -618,9 +757,12 @@ impl Parser {
                         let object = g.object;
                         return Ok(Expr::Set(Set {object, name, value: Box::new(value)}));
                     }
+                    Expr::Index(i) => {
+                        return Ok(Expr::IndexSet(IndexSet {object: i.object, index: i.index, value: Box::new(value), token: i.token}));
+                    }
                     _ => {}
                 };
-                Err(LoxError {kind: LoxErrorKind::SyntaxError(tokens.peek().unwrap().line), message: "invalid assignment target"})
+                Err(LoxError::new(LoxErrorKind::SyntaxError, "invalid assignment target").with_span(target_span))
             },
             _ => {
                 Ok(expr)
@@ -642,7 +784,7 @@ impl Parser {
                 _ => break
             }
             let right = self.and(tokens)?;
-            expr = Expr::Logical(Logical {token: token.to_owned(), operator: operator, left: Box::new(expr), right: Box::new(right)});
+            expr = Expr::Logical(Logical {token: token.to_owned(), operator, left: Box::new(expr), right: Box::new(right)});
         };
         Ok(expr)
     }
@@ -661,7 +803,7 @@ impl Parser {
                 _ => break
             }
             let right = self.equality(tokens)?;
-            expr = Expr::Logical(Logical {token: token.to_owned(), operator: operator, left: Box::new(expr), right: Box::new(right)});
+            expr = Expr::Logical(Logical {token: token.to_owned(), operator, left: Box::new(expr), right: Box::new(right)});
         };
         Ok(expr)
     }
@@ -684,7 +826,7 @@ impl Parser {
                 _ => break
             }
             let right = self.comparison(tokens)?;
-            expr = Expr::Binary(Binary {token: token.to_owned(), operator: operator, left: Box::new(expr), right: Box::new(right)});
+            expr = Expr::Binary(Binary {token: token.to_owned(), operator, left: Box::new(expr), right: Box::new(right)});
         };
         Ok(expr)
     }
@@ -715,7 +857,7 @@ impl Parser {
                 _ => break
             }
             let right = self.term(tokens)?;
-            expr = Expr::Binary(Binary {token: token.to_owned(), operator: operator, left: Box::new(expr), right: Box::new(right)});
+            expr = Expr::Binary(Binary {token: token.to_owned(), operator, left: Box::new(expr), right: Box::new(right)});
         };
         Ok(expr)
     }
@@ -738,12 +880,12 @@ impl Parser {
                 _ => break
             }
             let right = self.factor(tokens)?;
-            expr = Expr::Binary(Binary {token: token.to_owned(), operator: operator, left: Box::new(expr), right: Box::new(right)});
+            expr = Expr::Binary(Binary {token: token.to_owned(), operator, left: Box::new(expr), right: Box::new(right)});
         }
         Ok(expr)
     }
 
-    // factor -> unary ( ( "/" | "*") unary )* ;
+    // factor -> unary ( ( "/" | "*" | "%" ) unary )* ;
     fn factor(&mut self, tokens: &mut Peekable<Iter<Token>>) -> Result<Expr, LoxError>{
         let mut expr = self.unary(tokens)?;
         loop {
@@ -758,36 +900,54 @@ impl Parser {
                     token = tokens.next().unwrap();
                     operator = BinaryOperator::Star;
                 },
+                TokenType::Percent => {
+                    token = tokens.next().unwrap();
+                    operator = BinaryOperator::Percent;
+                },
                 _ => break
             }
             let right = self.unary(tokens)?;
-            expr = Expr::Binary(Binary {token: token.to_owned(), operator: operator, left: Box::new(expr), right: Box::new(right)});
+            expr = Expr::Binary(Binary {token: token.to_owned(), operator, left: Box::new(expr), right: Box::new(right)});
         }
         Ok(expr)
     }
 
-    // unary -> ( "!" | "-" ) unary | call ;
+    // unary -> ( "!" | "-" ) unary | exponent ;
     fn unary(&mut self, tokens: &mut Peekable<Iter<Token>>) -> Result<Expr, LoxError> {
         match &tokens.peek().unwrap().token_type {
             TokenType::Bang => {
                 let token = tokens.next().unwrap();
                 let operator = UnaryOperator::Bang;
                 let right = self.unary(tokens)?;
-                Ok(Expr::Unary(Unary {operator: operator, token: token.to_owned(), right: Box::new(right)}))
+                Ok(Expr::Unary(Unary {operator, token: token.to_owned(), right: Box::new(right)}))
             },
             TokenType::Minus => {
                 let token = tokens.next().unwrap();
                 let operator = UnaryOperator::Minus;
                 let right = self.unary(tokens)?;
-                Ok(Expr::Unary(Unary {operator: operator, token: token.to_owned(), right: Box::new(right)}))
+                Ok(Expr::Unary(Unary {operator, token: token.to_owned(), right: Box::new(right)}))
             }
             _ => {
-                self.call(tokens)
+                self.exponent(tokens)
             }
         }
     }
+
+    // exponent -> call ( "**" unary )? ;
+    // "**" is right associative and binds tighter than a unary operator on its left, so -2 ** 2 is -4
+    fn exponent(&mut self, tokens: &mut Peekable<Iter<Token>>) -> Result<Expr, LoxError> {
+        let expr = self.call(tokens)?;
+        match &tokens.peek().unwrap().token_type {
+            TokenType::StarStar => {
+                let token = tokens.next().unwrap();
+                let right = self.unary(tokens)?;
+                Ok(Expr::Binary(Binary {token: token.to_owned(), operator: BinaryOperator::StarStar, left: Box::new(expr), right: Box::new(right)}))
+            },
+            _ => Ok(expr)
+        }
+    }
     
-    // call -> primary ( "(" arguments? ")" |  "." IDENTIFIER )* ;
+    // call -> primary ( "(" arguments? ")" |  "." IDENTIFIER | "[" expression "]" )* ;
     fn call(&mut self, tokens: &mut Peekable<Iter<Token>>) -> Result<Expr, LoxError> {
         let mut expr = self.primary(tokens)?;
         loop {
@@ -810,7 +970,7 @@ impl Parser {
                                     token = tokens.next().unwrap().to_owned(); // consume ")"
                                 },
                                 _ => {
-                                    return Err(LoxError {kind: LoxErrorKind::ScannerError, message: "expected ')' call"})
+                                    return Err(expected("')' after arguments", tokens.peek().unwrap()))
                                 }
                             }
                         }
@@ -825,7 +985,20 @@ impl Parser {
                             expr = Expr::Get(Get { object: Box::new(expr), name });
                         },
                         _ => {
-                            return Err(LoxError {kind: LoxErrorKind::ScannerError, message: "expected identifier after '.'"})
+                            return Err(expected("identifier after '.'", tokens.peek().unwrap()))
+                        }
+                    }
+                },
+                TokenType::LeftBracket => {
+                    tokens.next(); // consume "["
+                    let index = self.expression(tokens)?;
+                    match &tokens.peek().unwrap().token_type {
+                        TokenType::RightBracket => {
+                            let token = tokens.next().unwrap().to_owned(); // consume "]"
+                            expr = Expr::Index(Index { object: Box::new(expr), index: Box::new(index), token });
+                        },
+                        _ => {
+                            return Err(expected("']' after index", tokens.peek().unwrap()))
                         }
                     }
                 },
@@ -845,7 +1018,7 @@ impl Parser {
                 // no need to return the Error
                 // that would mean the parser is in a bad state and needs to synchronize
                 // but we don't need to do that for this type of error
-                self.errors.push(LoxError {kind: LoxErrorKind::SyntaxError(tokens.peek().unwrap().line), message: "can't have > 255 arguments to a function call"})
+                self.errors.push(LoxError::new(LoxErrorKind::SyntaxError, "can't have > 255 arguments to a function call").with_span(tokens.peek().unwrap().span))
             }
             args.push(self.expression(tokens)?);
             match &tokens.peek().unwrap().token_type {
@@ -860,7 +1033,9 @@ impl Parser {
         Ok(args)
     }
 
-    // primary -> NUMBER | STRING | "true" | "false" | "nil" | "(" expression ")" | "this" | "super" "." IDENTIFIER ;
+    // primary -> NUMBER | STRING | "true" | "false" | "nil" | "(" expression ")" | "this" | "super" "." IDENTIFIER
+    //  | "[" arguments? "]" | "{" ( entry ( "," entry )* )? "}" | lambda | arrowFunction | interpolation ;
+    // entry -> expression ":" expression ;
     fn primary(&mut self, tokens: &mut Peekable<Iter<Token>>) -> Result<Expr, LoxError> {
         match &tokens.peek().unwrap().token_type {
             TokenType::False | TokenType::True | TokenType::Number | TokenType::String | TokenType::Nil => {
@@ -883,18 +1058,27 @@ impl Parser {
                                 Ok(Expr::Super(Super { keyword, method }))
                             },
                             _ => {
-                                Err(LoxError {kind: LoxErrorKind::ScannerError, message: "expected superclass method name after '.'"})
+                                Err(expected("superclass method name after '.'", tokens.peek().unwrap()))
                             }
                         }
                     }, 
                     _ => {
-                        Err(LoxError {kind: LoxErrorKind::ScannerError, message: "expected '.' after 'super' keyword"})
+                        Err(expected("'.' after 'super' keyword", tokens.peek().unwrap()))
                     }
                 }
             },
             TokenType::Identifier => {
                 Ok(Expr::Variable(Variable { token: tokens.next().unwrap().to_owned() }))
             },
+            TokenType::Interpolation => {
+                self.interpolation(tokens)
+            },
+            TokenType::Fun => {
+                self.lambda(tokens)
+            },
+            TokenType::LeftParen if is_arrow_function(tokens) => {
+                self.arrow_function(tokens)
+            },
             TokenType::LeftParen => {
                 tokens.next(); // consume '('
                 let expr = self.expression(tokens)?;
@@ -903,14 +1087,135 @@ impl Parser {
                         tokens.next() // consume matching ')'
                     },
                     _ => {
-                        return Err(LoxError {kind: LoxErrorKind::ScannerError, message: "expected ')' after expression"})
+                        return Err(expected("')' after expression", tokens.peek().unwrap()))
                     }
                 };
                 Ok(Expr::Grouping(Grouping {expr: Box::new(expr)}))
             }
+            TokenType::LeftBracket => {
+                let token = tokens.next().unwrap().to_owned(); // consume '['
+                let elements = match &tokens.peek().unwrap().token_type {
+                    TokenType::RightBracket => Vec::new(),
+                    _ => self.arguments(tokens)?
+                };
+                let closing = match &tokens.peek().unwrap().token_type {
+                    TokenType::RightBracket => {
+                        tokens.next().unwrap().to_owned() // consume ']'
+                    },
+                    _ => {
+                        return Err(expected("']' after list elements", tokens.peek().unwrap()))
+                    }
+                };
+                Ok(Expr::List(List { token, elements, closing }))
+            }
+            TokenType::LeftBrace => {
+                let token = tokens.next().unwrap().to_owned(); // consume '{'
+                let mut entries = Vec::new();
+                loop {
+                    if let TokenType::RightBrace = &tokens.peek().unwrap().token_type {
+                        break;
+                    }
+                    let key = self.expression(tokens)?;
+                    match &tokens.peek().unwrap().token_type {
+                        TokenType::Colon => {
+                            tokens.next(); // consume ':'
+                        },
+                        _ => {
+                            return Err(expected("':' after map key", tokens.peek().unwrap()))
+                        }
+                    };
+                    let value = self.expression(tokens)?;
+                    entries.push((key, value));
+                    match &tokens.peek().unwrap().token_type {
+                        TokenType::Comma => {
+                            tokens.next(); // consume ','
+                        },
+                        _ => break
+                    }
+                }
+                let closing = match &tokens.peek().unwrap().token_type {
+                    TokenType::RightBrace => {
+                        tokens.next().unwrap().to_owned() // consume '}'
+                    },
+                    _ => {
+                        return Err(expected("'}' after map entries", tokens.peek().unwrap()))
+                    }
+                };
+                Ok(Expr::Map(Map { token, entries, closing }))
+            }
             _ => {
-                Err(LoxError {kind: LoxErrorKind::SyntaxError(tokens.peek().unwrap().line), message: "invalid syntax"})
+                Err(expected("expression", tokens.peek().unwrap()))
+            }
+        }
+    }
+
+    // lambda -> "fun" "(" parameters? ")" blockStatement ;
+    fn lambda(&mut self, tokens: &mut Peekable<Iter<Token>>) -> Result<Expr, LoxError> {
+        let token = tokens.next().unwrap().to_owned(); // consume 'fun'
+        match &tokens.peek().unwrap().token_type {
+            TokenType::LeftParen => {},
+            // ex: `var f = fun named() {};`, only statements can declare named functions
+            TokenType::Identifier => {
+                return Err(LoxError::new(LoxErrorKind::SyntaxError, "anonymous functions cannot be named").with_span(tokens.peek().unwrap().span))
             }
+            _ => return Err(expected("'(' after 'fun'", tokens.peek().unwrap())),
         }
+        let parameters = self.parameters(tokens)?;
+        let body = match &tokens.peek().unwrap().token_type {
+            TokenType::LeftBrace => self.block(tokens)?,
+            _ => {
+                return Err(expected("'{' before function body", tokens.peek().unwrap()))
+            }
+        };
+        let declaration = Rc::new(FunDeclStatement {name: anonymous(&token), parameters, body});
+        Ok(Expr::Lambda(Lambda { token, declaration }))
     }
-}
\ No newline at end of file
+
+    // arrowFunction -> "(" parameters? ")" "=>" ( blockStatement | expression ) ;
+    fn arrow_function(&mut self, tokens: &mut Peekable<Iter<Token>>) -> Result<Expr, LoxError> {
+        let parameters = self.parameters(tokens)?;
+        let token = tokens.next().unwrap().to_owned(); // consume '=>'
+        let body = match &tokens.peek().unwrap().token_type {
+            TokenType::LeftBrace => self.block(tokens)?,
+            _ => {
+                // an expression body is returned
+                let value = self.expression(tokens)?;
+                vec![Statement::ReturnStatement(ReturnStatement { keyword: token.clone(), value: Some(value) })]
+            }
+        };
+        let declaration = Rc::new(FunDeclStatement {name: anonymous(&token), parameters, body});
+        Ok(Expr::Lambda(Lambda { token, declaration }))
+    }
+
+    // interpolation -> INTERPOLATION expression ( INTERPOLATION_MIDDLE expression )* INTERPOLATION_END ;
+    // the scanner splits an interpolated string into the text around each "${...}",
+    //  with the tokens of the expression inside it in between
+    fn interpolation(&mut self, tokens: &mut Peekable<Iter<Token>>) -> Result<Expr, LoxError> {
+        let token = (*tokens.peek().unwrap()).to_owned();
+        let mut parts = Vec::new();
+        loop {
+            let text = tokens.next().unwrap().to_owned(); // consume the string before "${", or after "}"
+            if let Some(LiteralValue::StringValue(s)) = &text.literal {
+                if !s.is_empty() {
+                    parts.push(Expr::Literal(Literal { value: LiteralValue::StringValue(s.to_owned()), token: text.clone() }));
+                }
+            }
+            if matches!(text.token_type, TokenType::InterpolationEnd) {
+                return Ok(Expr::Interpolation(Interpolation { token, parts, closing: text }));
+            }
+            let next = tokens.peek().unwrap();
+            if matches!(next.token_type, TokenType::InterpolationMiddle | TokenType::InterpolationEnd) {
+                // an empty "${}", point at its '}'
+                let span = Span { end: next.span.start + 1, ..next.span };
+                return Err(LoxError::new(LoxErrorKind::SyntaxError, "expected expression inside '${}', found '}'").with_span(span))
+            }
+            parts.push(self.expression(tokens)?);
+            match &tokens.peek().unwrap().token_type {
+                TokenType::InterpolationMiddle | TokenType::InterpolationEnd => {},
+                _ => {
+                    return Err(expected("'}' after interpolated expression", tokens.peek().unwrap()))
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,30 @@
+use crate::{error::{LoxError, LoxErrorKind}, native::NativeFunction, tree_walker::Value};
+
+/// The natives for talking to the process running the script, ex: `args`, to be defined as globals.
+pub fn natives() -> Vec<NativeFunction> {
+    vec![
+        NativeFunction::with_runtime("args", 0, |interpreter, _| {
+            let args = interpreter.args.iter().map(|arg| Value::StringValue(arg.to_owned())).collect();
+            Ok(Value::ListValue(interpreter.heap.list(args)))
+        }),
+        NativeFunction::new("exit", 1, |arguments| {
+            // unwinds the whole script, it's up to whoever is running it to actually exit
+            match &arguments[0] {
+                Value::NumberValue(n) if n.fract() == 0.0 && *n >= 0.0 && *n <= 255.0 => {
+                    let code = *n as i32;
+                    Err(LoxError::new(LoxErrorKind::Exit(code), format!("exit({})", code)))
+                }
+                other => Err(LoxError::new(LoxErrorKind::TypeError, format!("exit expects a whole number from 0 to 255, got {}", other.repr())))
+            }
+        }),
+        NativeFunction::with_runtime("env", 1, |interpreter, arguments| {
+            if !interpreter.capabilities.environment {
+                return Err(LoxError::new(LoxErrorKind::IOError, "Can't call env, reading environment variables is disabled"))
+            }
+            match &arguments[0] {
+                Value::StringValue(name) => Ok(std::env::var(name).map_or(Value::NilValue, Value::StringValue)),
+                other => Err(LoxError::new(LoxErrorKind::TypeError, format!("Argument to env must be a string, got {}", other.type_name())))
+            }
+        }),
+    ]
+}
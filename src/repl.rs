@@ -0,0 +1,85 @@
+//! Helpers for running Lox interactively.
+
+use crate::{ast::Expr, error::LoxErrorKind, parse::Parser, scan::Scanner, tokens::TokenType};
+
+/// If `source` is a single expression, optionally followed by a `;`, returns the expression's source.
+///
+/// Assignments are left out, so typing `x = 1;` in a REPL doesn't echo `1`.
+pub fn lone_expression(source: &str) -> Option<&str> {
+    let mut scanner = Scanner::new();
+    scanner.scan(source).ok()?;
+    let mut tokens = scanner.tokens;
+    let mut end = source.len();
+    // the last token is always EOF
+    if tokens.len() >= 2 && matches!(tokens[tokens.len() - 2].token_type, TokenType::Semicolon) {
+        end = tokens.remove(tokens.len() - 2).span.start;
+    }
+    match Parser::new().parse_expression(&tokens).ok()? {
+        Expr::Assignment(_) | Expr::Set(_) | Expr::IndexSet(_) => None,
+        _ => Some(&source[..end]),
+    }
+}
+
+/// Whether `source` stops partway through a statement, so a REPL should keep reading lines
+/// instead of running it, ex: an unclosed `{` or a statement that's missing its `;`.
+///
+/// Source with a mistake before its end isn't incomplete, since more input can't fix it,
+/// and neither is a lone expression without a `;` (see [`lone_expression`]).
+pub fn is_incomplete(source: &str) -> bool {
+    if lone_expression(source).is_some() {
+        return false;
+    }
+    let mut scanner = Scanner::new();
+    if let Err(error) = scanner.scan(source) {
+        // strings (and the "${" in them) can span lines
+        return matches!(error.kind, LoxErrorKind::ScannerError) && error.message.starts_with("unterminated");
+    }
+    let mut parser = Parser::new();
+    if parser.parse(&scanner.tokens).is_ok() {
+        return false;
+    }
+    // the parser only ran out of tokens if its first error is at the end of the source
+    match parser.errors.first().and_then(|error| error.span) {
+        Some(span) => span.start == source.len(),
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{is_incomplete, lone_expression};
+
+    #[test]
+    fn unfinished_statements_are_incomplete() {
+        assert!(is_incomplete("class Foo {"));
+        assert!(is_incomplete("fun add(a, b) {\n  return a + b;"));
+        assert!(is_incomplete("print 1 +"));
+        assert!(is_incomplete("var x = 1"));
+        assert!(is_incomplete("print \"first line\n"));
+        assert!(is_incomplete("print \"${1 +"));
+    }
+
+    #[test]
+    fn finished_or_broken_statements_are_complete() {
+        assert!(!is_incomplete("print 1;"));
+        assert!(!is_incomplete("fun add(a, b) {\n  return a + b;\n}"));
+        assert!(!is_incomplete(""));
+        // no amount of extra input fixes these
+        assert!(!is_incomplete("print );"));
+        assert!(!is_incomplete("}"));
+        assert!(!is_incomplete("var x = $"));
+        assert!(!is_incomplete("1 + 2"));
+    }
+
+    #[test]
+    fn lone_expressions_are_found_with_or_without_a_semicolon() {
+        assert_eq!(lone_expression("1 + 2"), Some("1 + 2"));
+        assert_eq!(lone_expression("1 + 2;\n"), Some("1 + 2"));
+        assert_eq!(lone_expression("[1, 2][0] ; "), Some("[1, 2][0] "));
+        assert_eq!(lone_expression("print 1;"), None);
+        assert_eq!(lone_expression("1; 2;"), None);
+        assert_eq!(lone_expression("x = 1;"), None);
+        assert_eq!(lone_expression("a.b = 1"), None);
+        assert_eq!(lone_expression("1 +"), None);
+    }
+}
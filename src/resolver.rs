@@ -1,6 +1,6 @@
 use std::collections::HashMap;
 
-use crate::{ast::{Assignment, Binary, BlockStatement, Call, ClassDeclStatement, Expr, ExpressionStatement, FunDeclStatement, Get, Grouping, IfStatement, Logical, PrintStatement, ReturnStatement, Set, Statement, Super, This, Unary, VarDeclStatement, Variable, WhileStatement}, error::LoxError, tokens::Token, tree_walker::TreeWalker};
+use crate::{ast::{Assignment, Binary, BlockStatement, BreakStatement, Call, ClassDeclStatement, ContinueStatement, Expr, ExpressionStatement, FunDeclStatement, Get, Grouping, IfStatement, Index, IndexSet, List, Logical, Map, PrintStatement, ReturnStatement, Set, Statement, Super, This, ThrowStatement, TryStatement, Unary, VarDeclStatement, Variable, WhileStatement}, error::LoxError, tokens::{Span, Token}, tree_walker::{LocalSlot, TreeWalker}};
 
 #[derive(Clone)]
 enum FunctionType {
@@ -17,22 +17,37 @@ enum ClassType {
     Subclass,
 }
 
+// what the resolver knows about a local variable
+struct Local {
+    // whether or not we have finished resolving the variable's initializer
+    defined: bool,
+    // where the variable was declared, `None` for implicit ones like `this`
+    declared_at: Option<Span>,
+    // its index in the environment of its scope, variables are numbered in the order they're declared
+    slot: usize,
+}
+
+#[derive(Clone)]
+enum LoopType {
+    None,
+    Loop,
+}
+
 pub struct Resolver<'i>{
-    // The value associated with a key in the scope map represents
-    //  whether or not we have finished resolving that variable’s initializer.
-    scopes: Vec<HashMap<String, bool>>,
-    pub(crate) errors: Vec<LoxError>,
+    scopes: Vec<HashMap<String, Local>>,
+    pub errors: Vec<LoxError>,
     interpreter: &'i mut TreeWalker,
     current_function: FunctionType,
     current_class: ClassType,
+    current_loop: LoopType,
 }
 
 impl<'i> Resolver<'i> {
-    pub(crate) fn new(interpreter: &'i mut TreeWalker) -> Resolver<'i> {
-        Resolver {scopes: Vec::new(), errors: Vec::new(), interpreter, current_function: FunctionType::None, current_class: ClassType::None }
+    pub fn new(interpreter: &'i mut TreeWalker) -> Resolver<'i> {
+        Resolver {scopes: Vec::new(), errors: Vec::new(), interpreter, current_function: FunctionType::None, current_class: ClassType::None, current_loop: LoopType::None }
     }
 
-    pub(crate) fn resolve(&mut self, statements: &Vec<Statement>) {
+    pub fn resolve(&mut self, statements: &Vec<Statement>) {
         for stmt in statements {
             self.resolve_statement(stmt);
         }
@@ -59,10 +74,14 @@ impl<'i> Resolver<'i> {
             Statement::FunDeclStatement(stmt) => { self.visit_fun_decl_statement(stmt) }
             Statement::ReturnStatement(stmt) => { self.visit_return_statement(stmt) }
             Statement::ClassDeclStatement(stmt) => { self.visit_class_decl_statement(stmt) }
+            Statement::BreakStatement(stmt) => { self.visit_break_statement(stmt) }
+            Statement::ContinueStatement(stmt) => { self.visit_continue_statement(stmt) }
+            Statement::TryStatement(stmt) => { self.visit_try_statement(stmt) }
+            Statement::ThrowStatement(stmt) => { self.visit_throw_statement(stmt) }
         }
     }
 
-    fn resolve_expression(&mut self, expression: &Expr) {
+    pub fn resolve_expression(&mut self, expression: &Expr) {
         match expression {
             Expr::Binary(b) => { self.visit_binary(b) }
             Expr::Unary(u) => { self.visit_unary(u) }
@@ -76,29 +95,36 @@ impl<'i> Resolver<'i> {
             Expr::Set(s) => { self.visit_set(s) }
             Expr::This(t) => { self.visit_this(t) }
             Expr::Super(s) => { self.visit_super(s) }
+            Expr::List(l) => { self.visit_list(l) }
+            Expr::Map(m) => { self.visit_map(m) }
+            Expr::Index(i) => { self.visit_index(i) }
+            Expr::IndexSet(i) => { self.visit_index_set(i) }
+            Expr::Lambda(l) => { self.resolve_function(&l.declaration, FunctionType::Function) }
+            Expr::Interpolation(i) => { i.parts.iter().for_each(|part| self.resolve_expression(part)) }
         }
     }
 
-    fn declare(&mut self, name: &String) {
+    fn declare(&mut self, name: &Token) {
         if let Some(scope) = self.scopes.last_mut()  {
-            if scope.contains_key(name) {
-                self.errors.push(LoxError {kind: crate::error::LoxErrorKind::ResolvingError,
-                    message: "Variable with this name already exists in this scope"});
+            if let Some(previous) = scope.get(&name.lexeme) {
+                self.errors.push(LoxError::new(crate::error::LoxErrorKind::ResolvingError, format!("Variable '{}' already exists in this scope", name.lexeme)).with_span(name.span)
+                    .with_note("previously declared here", previous.declared_at));
             }
-            scope.insert(name.to_owned(), false);
+            let slot = scope.len();
+            scope.insert(name.lexeme.to_owned(), Local { defined: false, declared_at: Some(name.span), slot });
         }
     }
 
-    fn define(&mut self, name: &String) {
-        if let Some(scope) = self.scopes.last_mut()  {
-            scope.insert(name.to_owned(), true);
+    fn define(&mut self, name: &Token) {
+        if let Some(local) = self.scopes.last_mut().and_then(|scope| scope.get_mut(&name.lexeme)) {
+            local.defined = true;
         }
     }
 
     fn resolve_local(&mut self, token: &Token) {
-        for (index, scope) in self.scopes.iter().rev().enumerate() {
-            if scope.contains_key(&token.lexeme) {
-                self.interpreter.resolve(token, index);
+        for (depth, scope) in self.scopes.iter().rev().enumerate() {
+            if let Some(local) = scope.get(&token.lexeme) {
+                self.interpreter.resolve(token, LocalSlot { depth, slot: local.slot });
                 break;
             }
         }
@@ -107,44 +133,49 @@ impl<'i> Resolver<'i> {
     fn resolve_function(&mut self, stmt: &FunDeclStatement, fun_type: FunctionType) {
         let enclosing_function = self.current_function.clone();
         self.current_function = fun_type;
+        // a loop around the function declaration doesn't make break/continue valid in its body
+        let enclosing_loop = self.current_loop.clone();
+        self.current_loop = LoopType::None;
 
         self.begin_scope();
         for param in &stmt.parameters {
-            self.declare(&param.lexeme);
-            self.define(&param.lexeme);
+            self.declare(param);
+            self.define(param);
         }
         for stmt in &stmt.body {
             self.resolve_statement(stmt);
         }
         self.end_scope();
         self.current_function = enclosing_function;
+        self.current_loop = enclosing_loop;
     }
 
     // AST nodes that need resolving
 
     fn visit_block_statement(&mut self, block: &BlockStatement) {
+        self.resolve_block(&block.statements);
+    }
+
+    fn resolve_block(&mut self, statements: &[Statement]) {
         self.begin_scope();
-        for statement in &block.statements {
+        for statement in statements {
             self.resolve_statement(statement);
         }
         self.end_scope();
     }
 
     fn visit_var_decl_statement(&mut self, stmt: &VarDeclStatement) {
-        self.declare(&stmt.token.lexeme);
-        match &stmt.initializer {
-            Some(init) => { self.resolve_expression(init) }
-            None => {}
-        };
-        self.define(&stmt.token.lexeme);
+        self.declare(&stmt.token);
+        if let Some(init) = &stmt.initializer { self.resolve_expression(init) };
+        self.define(&stmt.token);
     }
 
     fn visit_variable(&mut self, expr: &Variable) {
         if let Some(scope) = self.scopes.last()  {
-            if let Some(finished_resolving) = scope.get(&expr.token.lexeme) {
-                if !finished_resolving {
-                    self.errors.push(LoxError {kind: crate::error::LoxErrorKind::ResolvingError,
-                         message: "Can't use local variable in its own intializer"});
+            if let Some(local) = scope.get(&expr.token.lexeme) {
+                if !local.defined {
+                    self.errors.push(LoxError::new(crate::error::LoxErrorKind::ResolvingError, format!("Can't use local variable '{}' in its own initializer", expr.token.lexeme)).with_span(expr.token.span)
+                         .with_note("variable declared here", local.declared_at));
                 }
             }
         }
@@ -157,8 +188,8 @@ impl<'i> Resolver<'i> {
     }
 
     fn visit_fun_decl_statement(&mut self, stmt: &FunDeclStatement) {
-        self.declare(&stmt.name.lexeme);
-        self.define(&stmt.name.lexeme);
+        self.declare(&stmt.name);
+        self.define(&stmt.name);
         self.resolve_function(stmt, FunctionType::Function);
     }
 
@@ -181,42 +212,76 @@ impl<'i> Resolver<'i> {
     }
 
     fn visit_return_statement(&mut self, stmt: &ReturnStatement) {
-        match self.current_function {
-            FunctionType::None => {
-                self.errors.push(LoxError {kind: crate::error::LoxErrorKind::ResolvingError,
-                    message: "Can't have a return statement in top level code"});
-            },
-            _ => {}
+        if let FunctionType::None = self.current_function {
+            self.errors.push(LoxError::new(crate::error::LoxErrorKind::ResolvingError, "Can't have a return statement in top level code").with_span(stmt.keyword.span));
         }
 
         if let Some(expr) = &stmt.value {
-            match self.current_function {
-                FunctionType::Initializer => {
-                    self.errors.push(LoxError {kind: crate::error::LoxErrorKind::ResolvingError,
-                        message: "Can't return a value from an initializer"});
-                },
-                _ => {}
+            if let FunctionType::Initializer = self.current_function {
+                self.errors.push(LoxError::new(crate::error::LoxErrorKind::ResolvingError, "Can't return a value from an initializer").with_span(stmt.keyword.span));
             }
             self.resolve_expression(expr);
         }
     }
 
     fn visit_while_statement(&mut self, stmt: &WhileStatement) {
+        let enclosing_loop = self.current_loop.clone();
+        self.current_loop = LoopType::Loop;
+
         self.resolve_expression(&stmt.condition);
         self.resolve_statement(stmt.body.as_ref());
+        if let Some(increment) = &stmt.increment {
+            self.resolve_expression(increment);
+        }
+
+        self.current_loop = enclosing_loop;
+    }
+
+    fn visit_break_statement(&mut self, stmt: &BreakStatement) {
+        if let LoopType::None = self.current_loop {
+            self.errors.push(LoxError::new(crate::error::LoxErrorKind::ResolvingError, "Can't use break outside of a loop").with_span(stmt.keyword.span));
+        }
+    }
+
+    fn visit_continue_statement(&mut self, stmt: &ContinueStatement) {
+        if let LoopType::None = self.current_loop {
+            self.errors.push(LoxError::new(crate::error::LoxErrorKind::ResolvingError, "Can't use continue outside of a loop").with_span(stmt.keyword.span));
+        }
+    }
+
+    fn visit_try_statement(&mut self, stmt: &TryStatement) {
+        self.resolve_block(&stmt.body);
+        if let Some(catch) = &stmt.catch {
+            // the exception variable lives in the same scope as the catch body,
+            // just like function parameters
+            self.begin_scope();
+            self.declare(&catch.name);
+            self.define(&catch.name);
+            for statement in &catch.body {
+                self.resolve_statement(statement);
+            }
+            self.end_scope();
+        }
+        if let Some(finally) = &stmt.finally {
+            self.resolve_block(finally);
+        }
+    }
+
+    fn visit_throw_statement(&mut self, stmt: &ThrowStatement) {
+        self.resolve_expression(&stmt.value);
     }
 
     fn visit_class_decl_statement(&mut self, stmt: &ClassDeclStatement) {
         let enclosing_class_type = self.current_class.clone();
         self.current_class = ClassType::Class;
 
-        self.declare(&stmt.name.lexeme);
-        self.define(&stmt.name.lexeme);
+        self.declare(&stmt.name);
+        self.define(&stmt.name);
         
         if let Some(superclass) = &stmt.superclass {
             if superclass.token.lexeme == stmt.name.lexeme {
-                self.errors.push(LoxError {kind: crate::error::LoxErrorKind::ResolvingError,
-                    message: "A class can't inherit from itself"});
+                self.errors.push(LoxError::new(crate::error::LoxErrorKind::ResolvingError, format!("Class '{}' can't inherit from itself", stmt.name.lexeme)).with_span(superclass.token.span)
+                    .with_note("class declared here", Some(stmt.name.span)));
             }
             self.current_class = ClassType::Subclass;
             self.visit_variable(superclass);
@@ -224,12 +289,12 @@ impl<'i> Resolver<'i> {
             // special scope that contains super keyword reference to superclass
             // this scope contains the scope that has all the class methods
             self.begin_scope();
-            self.scopes.last_mut().unwrap().insert(String::from("super"), true);
+            self.scopes.last_mut().unwrap().insert(String::from("super"), Local { defined: true, declared_at: None, slot: 0 });
         }
 
 
         self.begin_scope();
-        self.scopes.last_mut().unwrap().insert(String::from("this"), true); // we just called begin_scope, so unwrap won't ever panic
+        self.scopes.last_mut().unwrap().insert(String::from("this"), Local { defined: true, declared_at: None, slot: 0 }); // we just called begin_scope, so unwrap won't ever panic
 
         for method in &stmt.methods {
             let fun_type = match method.name.lexeme.as_str() {
@@ -241,7 +306,7 @@ impl<'i> Resolver<'i> {
 
         self.end_scope();
 
-        if let Some(_) = &stmt.superclass {
+        if stmt.superclass.is_some() {
             self.end_scope();
         }
 
@@ -256,7 +321,7 @@ impl<'i> Resolver<'i> {
     fn visit_call(&mut self, expr: &Call) {
         self.resolve_expression(expr.callee.as_ref());
         for argument in &expr.arguments {
-            self.resolve_expression(&argument);
+            self.resolve_expression(argument);
         }
     }
 
@@ -275,8 +340,7 @@ impl<'i> Resolver<'i> {
                 self.resolve_local(&expr.keyword)
             },
             ClassType::None => {
-                self.errors.push(LoxError {kind: crate::error::LoxErrorKind::ResolvingError,
-                    message: "Can't use this keyword outside of a class"});
+                self.errors.push(LoxError::new(crate::error::LoxErrorKind::ResolvingError, "Can't use 'this' outside of a class").with_span(expr.keyword.span));
             }
         }
     }
@@ -284,18 +348,40 @@ impl<'i> Resolver<'i> {
     fn visit_super(&mut self, expr: &Super) {
         match self.current_class {
             ClassType::None => {
-                self.errors.push(LoxError {kind: crate::error::LoxErrorKind::ResolvingError,
-                    message: "Can't use super keyword outside of a class"});
+                self.errors.push(LoxError::new(crate::error::LoxErrorKind::ResolvingError, "Can't use 'super' outside of a class").with_span(expr.keyword.span));
             }
             ClassType::Class => {
-                self.errors.push(LoxError {kind: crate::error::LoxErrorKind::ResolvingError,
-                    message: "Can't use super keyword inside a class with no superclass"});
+                self.errors.push(LoxError::new(crate::error::LoxErrorKind::ResolvingError, "Can't use 'super' in a class with no superclass").with_span(expr.keyword.span));
             }
             ClassType::Subclass => {}
         };
         self.resolve_local(&expr.keyword);
     }
 
+    fn visit_list(&mut self, expr: &List) {
+        for element in &expr.elements {
+            self.resolve_expression(element);
+        }
+    }
+
+    fn visit_map(&mut self, expr: &Map) {
+        for (key, value) in &expr.entries {
+            self.resolve_expression(key);
+            self.resolve_expression(value);
+        }
+    }
+
+    fn visit_index(&mut self, expr: &Index) {
+        self.resolve_expression(expr.object.as_ref());
+        self.resolve_expression(expr.index.as_ref());
+    }
+
+    fn visit_index_set(&mut self, expr: &IndexSet) {
+        self.resolve_expression(expr.value.as_ref());
+        self.resolve_expression(expr.object.as_ref());
+        self.resolve_expression(expr.index.as_ref());
+    }
+
     fn visit_grouping(&mut self, expr: &Grouping) {
         self.resolve_expression(expr.expr.as_ref());
     }
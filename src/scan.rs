@@ -1,23 +1,35 @@
 use std::iter::Peekable;
 use std::str::Chars;
 
-use crate::{error::{LoxError, LoxErrorKind}, tokens::{LiteralValue, Token}};
+use crate::{error::{LoxError, LoxErrorKind}, tokens::{LiteralValue, Span, Token}};
 use crate::tokens::TokenType;
 
-pub(crate) struct Scanner {
+#[derive(Debug)]
+pub struct Scanner {
     pub tokens: Vec<Token>,
-    start: usize,
+    start: usize, // byte offsets into the source
     current: usize,
-    line: i32,
+    line: usize, // position of `current`
+    column: usize,
+    start_line: usize, // position of `start`
+    start_column: usize,
     next_id: u32,
+    // for each "${" being scanned, innermost last, how many '{' inside it are still open
+    interpolations: Vec<usize>,
+}
+
+impl Default for Scanner {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl<'c> Scanner {
     pub fn new() -> Scanner {
-        Scanner { tokens: Vec::<Token>::new(), start: 0, current: 0, line: 1, next_id: 0 }
+        Scanner { tokens: Vec::<Token>::new(), start: 0, current: 0, line: 1, column: 1, start_line: 1, start_column: 1, next_id: 0, interpolations: Vec::new() }
     }
 
-    pub fn scan(&mut self, source: &'c String) -> Result<(), LoxError> {
+    pub fn scan(&mut self, source: &'c str) -> Result<(), LoxError> {
         // needed since scan can be called more than once for a given Scanner
         //  if the interpreter is running as a REPL
         // however we purposefully do not re-set next_id since IDs should be unique for ever token
@@ -25,6 +37,10 @@ impl<'c> Scanner {
         self.start = 0;
         self.current = 0;
         self.line = 1;
+        self.column = 1;
+        self.start_line = 1;
+        self.start_column = 1;
+        self.interpolations = Vec::new();
 
         let mut chars = source.chars().peekable();
         
@@ -34,31 +50,65 @@ impl<'c> Scanner {
             }
             self.scan_token(&mut chars, source)?;
             self.start = self.current;
+            self.start_line = self.line;
+            self.start_column = self.column;
+        }
+        if !self.interpolations.is_empty() {
+            return Err(LoxError::new(LoxErrorKind::ScannerError, "unterminated string interpolation, expected '}'").with_span(self.span()));
         }
         self.add_token(TokenType::EOF, "".to_owned(), None);
         Ok(())
     }
 
-    fn scan_token(&mut self, chars: &mut Peekable<Chars<'_>>, source: &'c String) -> Result<(), LoxError> {
+    fn scan_token(&mut self, chars: &mut Peekable<Chars<'_>>, source: &'c str) -> Result<(), LoxError> {
         // we can unwrap here, since we peeked before this and know that the result is Some not None
         let s = self.advance(chars).unwrap();
         match s {
             '(' => self.add_simple_token(TokenType::LeftParen, source),
             ')' => self.add_simple_token(TokenType::RightParen, source),
-            '{' => self.add_simple_token(TokenType::LeftBrace, source),
-            '}' => self.add_simple_token(TokenType::RightBrace, source),
+            '{' => {
+                if let Some(open) = self.interpolations.last_mut() {
+                    *open += 1;
+                }
+                self.add_simple_token(TokenType::LeftBrace, source);
+            },
+            '}' => {
+                match self.interpolations.last_mut() {
+                    Some(0) => {
+                        // closes a "${", so the rest of the string it was in follows
+                        self.interpolations.pop();
+                        return self.scan_string(chars, source, true);
+                    },
+                    Some(open) => *open -= 1,
+                    None => {},
+                }
+                self.add_simple_token(TokenType::RightBrace, source);
+            },
+            '[' => self.add_simple_token(TokenType::LeftBracket, source),
+            ']' => self.add_simple_token(TokenType::RightBracket, source),
             ',' => self.add_simple_token(TokenType::Comma, source),
             '.' => self.add_simple_token(TokenType::Dot, source),
             '-' => self.add_simple_token(TokenType::Minus, source),
             '+' => self.add_simple_token(TokenType::Plus, source),
             ';' => self.add_simple_token(TokenType::Semicolon, source),
-            '*' => self.add_simple_token(TokenType::Star, source),
+            ':' => self.add_simple_token(TokenType::Colon, source),
+            '%' => self.add_simple_token(TokenType::Percent, source),
+            '*' => {
+                let tt = if self.match_next('*', chars) { TokenType::StarStar } else { TokenType::Star };
+                self.add_simple_token(tt, source);
+            },
             '!' => {
                 let tt = if self.match_next('=', chars) { TokenType::BangEqual } else { TokenType::Bang };
                 self.add_simple_token(tt, source);
             },
             '=' => {
-                let tt = if self.match_next('=', chars) { TokenType::EqualEqual } else { TokenType::Equal };
+                let tt = if self.match_next('=', chars) {
+                    TokenType::EqualEqual
+                } else if self.match_next('>', chars) {
+                    TokenType::Arrow
+                } else {
+                    TokenType::Equal
+                };
                 self.add_simple_token(tt, source);
             },
             '<' => {
@@ -72,34 +122,29 @@ impl<'c> Scanner {
             '/' => {
                 if self.match_next('/', chars) {
                     // if you see '//' keep consuming characters until '\n'
-                    loop {
-                        if let Some(c) = chars.peek() {
-                            if c == &'\n' {
-                                break;
-                            } else {
-                                self.advance(chars);
-                            }
+                    while let Some(c) = chars.peek() {
+                        if c == &'\n' {
+                            break;
+                        } else {
+                            self.advance(chars);
                         }
                     }
                 } else {
                     self.add_simple_token(TokenType::Slash, source);
                 }
             },
-            ' ' | '\t' | '\r' => {},
-            '\n' => {
-                self.line += 1;
-            },
+            ' ' | '\t' | '\r' | '\n' => {},
             '"' => {
-                return self.scan_string(chars, source);
+                return self.scan_string(chars, source, false);
             }
             '0' | '1' | '2' | '3' | '4' | '5' | '6' | '7' | '8' | '9' => {
                 return self.scan_number(chars, source)
             }
             _ => {
-                if s.is_alphabetic() {
+                if s.is_alphabetic() || s == '_' {
                     return self.scan_alphabetic(chars, source)
                 } else {
-                    return Err(LoxError { kind: crate::error::LoxErrorKind::ScannerError, message: "unexpected character" })
+                    return Err(LoxError::new(crate::error::LoxErrorKind::ScannerError, format!("unexpected character '{}'", s)).with_span(self.span()))
                 }
             }
         }
@@ -107,8 +152,22 @@ impl<'c> Scanner {
     }
 
     fn advance(&mut self, chars: &mut Peekable<Chars<'_>>) -> Option<char> {
-        self.current += 1;
-        chars.next()
+        let next = chars.next();
+        if let Some(c) = next {
+            self.current += c.len_utf8();
+            if c == '\n' {
+                self.line += 1;
+                self.column = 1;
+            } else {
+                self.column += 1;
+            }
+        }
+        next
+    }
+
+    // the span of the token currently being scanned
+    fn span(&self) -> Span {
+        Span { start: self.start, end: self.current, line: self.start_line, column: self.start_column }
     }
 
     fn match_next(&mut self, expected: char, chars: &mut Peekable<Chars<'_>>) -> bool {
@@ -118,86 +177,134 @@ impl<'c> Scanner {
                 return true;
             }
         }
-        return false;
+        false
     }
 
-    fn add_simple_token(&mut self, token_type: TokenType, source: &'c String) {
+    fn add_simple_token(&mut self, token_type: TokenType, source: &'c str) {
         let lexeme = &source[self.start..self.current];
         self.add_token(token_type, lexeme.to_owned(), None);
     }
 
     fn add_token(&mut self, token_type: TokenType, lexeme: String, literal: Option<LiteralValue>) {
-        let t = Token {token_type, lexeme, literal, line: self.line, id: self.next_id};
+        let t = Token {token_type, lexeme, literal, span: self.span(), id: self.next_id};
         self.next_id += 1;
         self.tokens.push(t);
     }
 
-    fn scan_string(&mut self, chars: &mut Peekable<Chars<'_>>, source: &'c String) -> Result<(), LoxError> {
+    // scans a string literal, or the part of one up to a "${". If `continued`, this is the part
+    // after the '}' closing a "${", which gets its own token types so the parser can tell it from a new string.
+    fn scan_string(&mut self, chars: &mut Peekable<Chars<'_>>, source: &'c str, continued: bool) -> Result<(), LoxError> {
+        // the literal is built as we go, since escape sequences make it differ from the source
+        let mut literal = String::new();
         loop {
             match self.advance(chars) {
+                Some('"') => {
+                    // reached end of string literal
+                    break;
+                },
+                Some('$') if self.match_next('{', chars) => {
+                    // the expression's tokens come next, then the rest of the string
+                    self.interpolations.push(0);
+                    let lexeme = &source[self.start..self.current];
+                    let token_type = if continued { TokenType::InterpolationMiddle } else { TokenType::Interpolation };
+                    self.add_token(token_type, lexeme.to_owned(), Some(LiteralValue::StringValue(literal)));
+                    return Ok(());
+                },
+                Some('\\') => {
+                    literal.push(self.scan_escape(chars)?);
+                },
                 Some(char) => {
-                    if char == '"' {
-                        // reached end of string literal
-                        break;
-                    } else {
-                        if char == '\n' {
-                            self.line += 1;
-                        }
-                    }
+                    literal.push(char);
                 },
                 None => {
-                    return Err(LoxError { kind: LoxErrorKind::ScannerError, message: "untermianted string "});
+                    return Err(LoxError::new(LoxErrorKind::ScannerError, "unterminated string").with_span(self.span()));
                 }
             }
         }
         let lexeme = &source[self.start..self.current];
-        // the lexeme includes the literal ", but we don't want the String to include this
-        //  so we don't include the first and last chars of the lexeme
-        let literal = String::from(&source[self.start+1..self.current-1]);
-        self.add_token(TokenType::String, lexeme.to_owned(), Some(LiteralValue::StringValue(literal)));
+        let token_type = if continued { TokenType::InterpolationEnd } else { TokenType::String };
+        self.add_token(token_type, lexeme.to_owned(), Some(LiteralValue::StringValue(literal)));
         Ok(())
     }
 
-    fn scan_number(&mut self, chars: &mut Peekable<Chars<'_>>, source: &'c String) -> Result<(), LoxError> {
-        loop {
-            if let Some(next) = chars.peek() {
-                match next {
-                    '0' | '1' | '2' | '3' | '4' | '5' | '6' | '7' | '8' | '9' => {
-                        self.advance(chars);
-                    },
-                    '.' => {
-                        let mut peek_more = chars.clone();
-                        peek_more.next(); // consume the '.' in this interator
-                        if let Some(after_dot) = peek_more.next() {
-                            match after_dot {
-                                '0' | '1' | '2' | '3' | '4' | '5' | '6' | '7' | '8' | '9' => {
-                                    self.advance(chars); // this consumes the '.'
-                                    // now keep consuming numbers as you see them
-                                    loop {
-                                        if let Some(number_after_dot) = chars.peek() {
-                                            match number_after_dot {
-                                                '0' | '1' | '2' | '3' | '4' | '5' | '6' | '7' | '8' | '9' => { 
-                                                    self.advance(chars);
-                                                },
-                                                _ => break
-                                            }
-                                        } else {
-                                            break;
-                                        }
+    // scans what follows a '\' in a string literal, returning the character it stands for
+    fn scan_escape(&mut self, chars: &mut Peekable<Chars<'_>>) -> Result<char, LoxError> {
+        // the '\' was just consumed and is never a newline, so it's one column back
+        let start = Span { start: self.current - 1, end: self.current, line: self.line, column: self.column - 1 };
+        let escaped = match self.advance(chars) {
+            Some('n') => '\n',
+            Some('t') => '\t',
+            Some('\\') => '\\',
+            Some('"') => '"',
+            Some('$') => '$',
+            Some('u') => return self.scan_unicode_escape(chars, start),
+            Some(other) => {
+                return Err(LoxError::new(LoxErrorKind::ScannerError, format!("invalid escape sequence '\\{}'", other)).with_span(Span { end: self.current, ..start }));
+            },
+            None => {
+                return Err(LoxError::new(LoxErrorKind::ScannerError, "unterminated string").with_span(self.span()));
+            }
+        };
+        Ok(escaped)
+    }
+
+    // scans the "{...}" of a '\u{...}' escape, which holds 1 to 6 hex digits
+    fn scan_unicode_escape(&mut self, chars: &mut Peekable<Chars<'_>>, start: Span) -> Result<char, LoxError> {
+        let mut digits = String::new();
+        let mut closed = false;
+        if self.match_next('{', chars) {
+            while let Some(c) = chars.peek() {
+                if c.is_ascii_hexdigit() {
+                    digits.push(*c);
+                    self.advance(chars);
+                } else {
+                    closed = self.match_next('}', chars);
+                    break;
+                }
+            }
+        }
+        let code_point = match u32::from_str_radix(&digits, 16) {
+            Ok(code_point) if closed && digits.len() <= 6 => char::from_u32(code_point),
+            _ => None,
+        };
+        code_point.ok_or_else(|| {
+            let span = Span { end: self.current, ..start };
+            LoxError::new(LoxErrorKind::ScannerError, "invalid unicode escape, expected '\\u{' followed by 1 to 6 hex digits and '}' naming a unicode character").with_span(span)
+        })
+    }
+
+    fn scan_number(&mut self, chars: &mut Peekable<Chars<'_>>, source: &'c str) -> Result<(), LoxError> {
+        while let Some(next) = chars.peek() {
+            match next {
+                '0' | '1' | '2' | '3' | '4' | '5' | '6' | '7' | '8' | '9' => {
+                    self.advance(chars);
+                },
+                '.' => {
+                    let mut peek_more = chars.clone();
+                    peek_more.next(); // consume the '.' in this interator
+                    if let Some(after_dot) = peek_more.next() {
+                        match after_dot {
+                            '0' | '1' | '2' | '3' | '4' | '5' | '6' | '7' | '8' | '9' => {
+                                self.advance(chars); // this consumes the '.'
+                                // now keep consuming numbers as you see them
+                                while let Some(number_after_dot) = chars.peek() {
+                                    match number_after_dot {
+                                        '0' | '1' | '2' | '3' | '4' | '5' | '6' | '7' | '8' | '9' => {
+                                            self.advance(chars);
+                                        },
+                                        _ => break
                                     }
-                                },
-                                _ => break
-                            }
-                        } else {
-                            break;
+                                }
+                            },
+                            _ => break
                         }
-                    },
-                    _ => {
+                    } else {
                         break;
                     }
+                },
+                _ => {
+                    break;
                 }
-            } else {
-                break;
             }
         }
 
@@ -208,18 +315,14 @@ impl<'c> Scanner {
             self.add_token(TokenType::Number, lexeme.to_owned(), literal);
             Ok(())
         } else {
-            Err(LoxError { kind: LoxErrorKind::ScannerError, message: "unable to parse float"})
+            Err(LoxError::new(LoxErrorKind::ScannerError, format!("unable to parse number '{}'", lexeme)).with_span(self.span()))
         }
     }
 
-    fn scan_alphabetic(&mut self, chars: &mut Peekable<Chars<'_>>, source: &'c String) -> Result<(), LoxError> {
-        loop {
-            if let Some(possible_alphabetic) = chars.peek() {
-                if possible_alphabetic.is_alphanumeric() {
-                    self.advance(chars);
-                } else {
-                    break;
-                }
+    fn scan_alphabetic(&mut self, chars: &mut Peekable<Chars<'_>>, source: &'c str) -> Result<(), LoxError> {
+        while let Some(possible_alphabetic) = chars.peek() {
+            if possible_alphabetic.is_alphanumeric() || *possible_alphabetic == '_' {
+                self.advance(chars);
             } else {
                 break;
             }
@@ -227,6 +330,12 @@ impl<'c> Scanner {
         let lexeme = &source[self.start..self.current];
         let token_type = match lexeme {
             "and" => TokenType::And,
+            "break" => TokenType::Break,
+            "continue" => TokenType::Continue,
+            "try" => TokenType::Try,
+            "catch" => TokenType::Catch,
+            "finally" => TokenType::Finally,
+            "throw" => TokenType::Throw,
             "class" => TokenType::Class,
             "else" => TokenType::Else,
             "false" => TokenType::False,
@@ -253,4 +362,49 @@ impl<'c> Scanner {
         self.add_token(token_type, lexeme.to_owned(), literal);
         Ok(())
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn string_literal(token: &Token) -> &str {
+        match &token.literal {
+            Some(LiteralValue::StringValue(s)) => s,
+            other => panic!("expected a string literal, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn invalid_escapes_point_at_the_escape() {
+        let mut scanner = Scanner::new();
+        let error = scanner.scan("var s = \"tab\\tbad\\q\";").unwrap_err();
+        assert_eq!(error.message, "invalid escape sequence '\\q'");
+        let span = error.span.unwrap();
+        assert_eq!((span.line, span.column), (1, 18));
+        assert_eq!((span.start, span.end), (17, 19));
+    }
+
+    #[test]
+    fn text_after_an_interpolation_has_its_own_token_types() {
+        let mut scanner = Scanner::new();
+        scanner.scan("\"a${x}b${y}c\" \"d\"").unwrap();
+        let types: Vec<&TokenType> = scanner.tokens.iter().map(|t| &t.token_type).collect();
+        assert!(matches!(types[..], [
+            TokenType::Interpolation, TokenType::Identifier, TokenType::InterpolationMiddle,
+            TokenType::Identifier, TokenType::InterpolationEnd, TokenType::String, TokenType::EOF,
+        ]), "{:?}", types);
+        assert_eq!(string_literal(&scanner.tokens[2]), "b");
+        assert_eq!(string_literal(&scanner.tokens[4]), "c");
+    }
+
+    #[test]
+    fn lines_are_counted_through_multi_line_strings() {
+        let mut scanner = Scanner::new();
+        scanner.scan("var s = \"one\ntwo\nthree\";\nprint s - 1;").unwrap();
+        assert_eq!(string_literal(&scanner.tokens[3]), "one\ntwo\nthree");
+        let print = &scanner.tokens[5];
+        assert!(matches!(print.token_type, TokenType::Print));
+        assert_eq!((print.span.line, print.span.column), (4, 1));
+    }
+}
@@ -0,0 +1,147 @@
+use crate::{error::{LoxError, LoxErrorKind}, native::NativeFunction, tree_walker::{TreeWalker, Value}};
+
+// a string method's implementation, given the string it was looked up on
+type StringMethod = fn(&mut TreeWalker, &str, Vec<Value>) -> Result<Value, LoxError>;
+
+/// Looks up a method on a string, as in `"abc".upper()`. It's a native function bound to that string,
+/// ex: the value of `"a,b".split`
+///
+/// Strings are indexed by character, not by byte, so `"héllo".len()` is 5.
+pub fn get_method(string: &str, name: &str) -> Result<Value, LoxError> {
+    let (arity, method): (usize, StringMethod) = match name {
+        "len" => (0, |_, string, _| Ok(Value::NumberValue(string.chars().count() as f64))),
+        "substring" => (2, substring),
+        "split" => (1, split),
+        "join" => (1, join),
+        "trim" => (0, |_, string, _| Ok(Value::StringValue(string.trim().to_owned()))),
+        "find" => (1, find),
+        "contains" => (1, |_, string, arguments| {
+            let needle = string_argument("contains", &arguments[0])?;
+            Ok(Value::BooleanValue(string.contains(needle)))
+        }),
+        "replace" => (2, replace),
+        "upper" => (0, |_, string, _| Ok(Value::StringValue(string.to_uppercase()))),
+        "lower" => (0, |_, string, _| Ok(Value::StringValue(string.to_lowercase()))),
+        "starts_with" => (1, |_, string, arguments| {
+            let prefix = string_argument("starts_with", &arguments[0])?;
+            Ok(Value::BooleanValue(string.starts_with(prefix)))
+        }),
+        "ends_with" => (1, |_, string, arguments| {
+            let suffix = string_argument("ends_with", &arguments[0])?;
+            Ok(Value::BooleanValue(string.ends_with(suffix)))
+        }),
+        "code_at" => (1, |_, string, arguments| {
+            let index = string_index(string, &arguments[0])?;
+            Ok(Value::NumberValue(string.chars().nth(index).unwrap() as u32 as f64))
+        }),
+        _ => return Err(LoxError::new(LoxErrorKind::AttributeError, format!("String has no method '{}'", name)))
+    };
+    let string = string.to_owned();
+    let native = NativeFunction::with_runtime(&format!("string.{}", name), arity, move |interpreter, arguments| method(interpreter, &string, arguments));
+    Ok(Value::Callable(Box::new(native)))
+}
+
+fn substring(_interpreter: &mut TreeWalker, string: &str, arguments: Vec<Value>) -> Result<Value, LoxError> {
+    let start = string_position(string, &arguments[0])?;
+    let end = string_position(string, &arguments[1])?;
+    if start > end {
+        return Err(LoxError::new(LoxErrorKind::IndexError, format!("Substring start {} must not be after its end {}", start, end)))
+    }
+    Ok(Value::StringValue(string[byte_offset(string, start)..byte_offset(string, end)].to_owned()))
+}
+
+fn split(interpreter: &mut TreeWalker, string: &str, arguments: Vec<Value>) -> Result<Value, LoxError> {
+    let separator = string_argument("split", &arguments[0])?;
+    let parts: Vec<Value> = if separator.is_empty() {
+        // splitting on nothing gives each character
+        string.chars().map(|c| Value::StringValue(c.to_string())).collect()
+    } else {
+        string.split(separator).map(|part| Value::StringValue(part.to_owned())).collect()
+    };
+    Ok(Value::ListValue(interpreter.heap.list(parts)))
+}
+
+fn join(_interpreter: &mut TreeWalker, string: &str, arguments: Vec<Value>) -> Result<Value, LoxError> {
+    let list = match &arguments[0] {
+        Value::ListValue(l) => l,
+        other => return Err(LoxError::new(LoxErrorKind::TypeError, format!("Argument to string.join must be a list, got {}", other.type_name())))
+    };
+    let parts: Vec<String> = list.borrow().iter().map(Value::to_string).collect();
+    Ok(Value::StringValue(parts.join(string)))
+}
+
+// the character index of the first match, or -1
+fn find(_interpreter: &mut TreeWalker, string: &str, arguments: Vec<Value>) -> Result<Value, LoxError> {
+    let needle = string_argument("find", &arguments[0])?;
+    let index = match string.find(needle) {
+        Some(offset) => string[..offset].chars().count() as f64,
+        None => -1.0,
+    };
+    Ok(Value::NumberValue(index))
+}
+
+fn replace(_interpreter: &mut TreeWalker, string: &str, arguments: Vec<Value>) -> Result<Value, LoxError> {
+    let from = string_argument("replace", &arguments[0])?;
+    let to = string_argument("replace", &arguments[1])?;
+    if from.is_empty() {
+        return Err(LoxError::new(LoxErrorKind::RuntimeError, "Can't replace an empty string"))
+    }
+    Ok(Value::StringValue(string.replace(from, to)))
+}
+
+/// Converts a Lox value to the index of a character in `string`, as in `string[index]`.
+pub fn string_index(string: &str, index: &Value) -> Result<usize, LoxError> {
+    let len = string.chars().count();
+    match index {
+        Value::NumberValue(n) => {
+            if n.fract() != 0.0 {
+                Err(LoxError::new(LoxErrorKind::TypeError, format!("String index must be a whole number, got {}", n)))
+            } else if *n < 0.0 || *n >= len as f64 {
+                Err(LoxError::new(LoxErrorKind::IndexError, format!("String index {} is out of range", n)))
+            } else {
+                Ok(*n as usize)
+            }
+        }
+        other => Err(LoxError::new(LoxErrorKind::TypeError, format!("String index must be a number, got {}", other.type_name())))
+    }
+}
+
+/// The character at `index` in `string`, as a string of its own.
+pub fn char_at(string: &str, index: &Value) -> Result<Value, LoxError> {
+    let index = string_index(string, index)?;
+    Ok(Value::StringValue(string.chars().nth(index).unwrap().to_string()))
+}
+
+// like string_index, but for positions between characters (used by substring)
+// so the string's length itself is allowed
+fn string_position(string: &str, position: &Value) -> Result<usize, LoxError> {
+    match position {
+        Value::NumberValue(n) if *n == string.chars().count() as f64 => Ok(*n as usize),
+        _ => string_index(string, position),
+    }
+}
+
+// the byte offset of the character at `position`, for slicing
+fn byte_offset(string: &str, position: usize) -> usize {
+    string.char_indices().nth(position).map_or(string.len(), |(offset, _)| offset)
+}
+
+fn string_argument<'a>(method: &str, argument: &'a Value) -> Result<&'a str, LoxError> {
+    match argument {
+        Value::StringValue(s) => Ok(s),
+        other => Err(LoxError::new(LoxErrorKind::TypeError, format!("Argument to string.{} must be a string, got {}", method, other.type_name())))
+    }
+}
+
+/// The native function `from_code(code)`, which makes a one character string from a unicode code point.
+/// It's the reverse of `string.code_at(index)`.
+pub fn from_code(arguments: Vec<Value>) -> Result<Value, LoxError> {
+    let character = match &arguments[0] {
+        Value::NumberValue(n) if n.fract() == 0.0 && *n >= 0.0 && *n <= u32::MAX as f64 => char::from_u32(*n as u32),
+        _ => None,
+    };
+    match character {
+        Some(c) => Ok(Value::StringValue(c.to_string())),
+        None => Err(LoxError::new(LoxErrorKind::TypeError, format!("from_code expects a unicode code point, got {}", arguments[0].repr())))
+    }
+}
@@ -1,31 +1,60 @@
+use std::fmt::Display;
+
 #[derive(Debug, Clone)]
 pub enum TokenType {                                   
     // Single-character tokens.                      
-    LeftParen, RightParen, LeftBrace, RightBrace,
-    Comma, Dot, Minus, Plus, Semicolon, Slash, Star, 
+    LeftParen, RightParen, LeftBrace, RightBrace, LeftBracket, RightBracket,
+    Comma, Dot, Minus, Plus, Semicolon, Colon, Slash, Star, Percent,
 
     // One or two character tokens.                  
     Bang, BangEqual,                                
     Equal, EqualEqual,                              
     Greater, GreaterEqual,                          
     Less, LessEqual,                                
+    Arrow, StarStar,
 
     // Literals.                                     
     Identifier, String, Number,
+    Interpolation, // the part of a string before a "${"
+    InterpolationMiddle, // the part of a string between the '}' closing a "${" and the next "${"
+    InterpolationEnd, // the part of a string after the '}' closing its last "${"
 
     // Keywords.                                     
     And, Class, Else, False, Fun, For, If, Nil, Or,  
-    Print, Return, Super, This, True, Var, While,    
+    Print, Return, Super, This, True, Var, While,
+    Break, Continue, Try, Catch, Finally, Throw,
 
     EOF                                              
 }
 
+/// A region of source code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Span {
+    pub start: usize, // byte offset of the first character
+    pub end: usize, // byte offset just past the last character
+    pub line: usize, // line of the first character, starting at 1
+    pub column: usize, // column (in characters, not bytes) of the first character, starting at 1
+}
+
+impl Span {
+    /// A span covering from the start of `self` to the end of `other`.
+    pub fn to(self, other: Span) -> Span {
+        Span { end: self.end.max(other.end), ..self }
+    }
+}
+
+impl Display for Span {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Token { 
     pub token_type: TokenType,
     pub lexeme: String,
     pub literal: Option<LiteralValue>,
-    pub line: i32,
+    pub span: Span,
     pub id: u32, // used for resolving names
 }
 
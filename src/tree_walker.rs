@@ -1,35 +1,83 @@
-use std::{cell::RefCell, collections::HashMap, fmt::{Display}, rc::Rc, usize};
+use std::{cell::RefCell, collections::HashMap, fmt::{Display}, rc::Rc};
 
-use crate::{ast::{Assignment, Binary, BinaryOperator, BlockStatement, Call, ClassDeclStatement, Expr, ExpressionStatement, FunDeclStatement, Get, IfStatement, Literal, Logical, LogicalOperator, PrintStatement, ReturnStatement, Set, Statement, Super, This, Unary, UnaryOperator, VarDeclStatement, Variable, WhileStatement}, callable::LoxCallable, class::{LoxClass, LoxInstance}, error::{LoxError, LoxErrorKind}, native::ClockCallable, tokens::{LiteralValue, Token}};
+use crate::{ast::{Assignment, Binary, BinaryOperator, BlockStatement, Call, ClassDeclStatement, Expr, ExpressionStatement, FunDeclStatement, Get, IfStatement, Index, IndexSet, Interpolation, Lambda, List, Literal, Logical, Map, LogicalOperator, PrintStatement, ReturnStatement, Set, Statement, Super, This, ThrowStatement, TryStatement, Unary, UnaryOperator, VarDeclStatement, Variable, WhileStatement}, callable::LoxCallable, class::{LoxClass, LoxInstance}, error::{LoxError, LoxErrorKind, StackFrame}, io::{self, Capabilities}, list::{self, ListRef}, math, map::{self, LoxMap, MapKey, MapRef}, native::{ClockCallable, NativeFunction}, process, string, tokens::{LiteralValue, Span, Token}};
 
 use crate::callable::Function;
+use crate::vm;
+use crate::gc::{Heap, HeapRef, Trace};
 
-#[cfg(test)]
-use crate::output::Recorder as Outputter;
-#[cfg(not(test))]
-use crate::output::Printer as Outputter;
+use crate::output::{OutputSink, Printer};
 
 #[derive(Debug)]
-pub(crate) struct TreeWalker {
-    pub environment: Rc<RefCell<Environment>>,
-    pub globals: Rc<RefCell<Environment>>,
-    pub outputter: Outputter,
-    pub locals: HashMap<u32, usize>,
+pub struct TreeWalker {
+    pub(crate) environment: Rc<RefCell<Environment>>,
+    pub(crate) globals: Rc<RefCell<Environment>>,
+    pub(crate) outputter: Box<dyn OutputSink>,
+    pub(crate) locals: HashMap<u32, LocalSlot>, // from the resolver, keyed by the id of the token using the variable
+    pub(crate) heap: Heap,
+    pub(crate) capabilities: Capabilities, // what natives may do outside the interpreter
+    pub(crate) args: Vec<String>, // returned by the args() native
+    pub(crate) call_stack: Vec<CallFrame>, // shared with the VM, which pushes its own calls here too
+    pub(crate) vm_stack: vm::Stack,
+    error_class: Rc<LoxClass>, // class of the values that errors are caught as
 }
 
+// a call that is currently executing
+#[derive(Debug)]
+pub(crate) struct CallFrame {
+    pub(crate) function: String,
+    pub(crate) call_site: Span,
+}
+
+/// Where the resolver found a local variable: `depth` environments up from the one
+/// the code using it runs in, at index `slot` of that environment's values.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LocalSlot {
+    pub depth: usize,
+    pub slot: usize,
+}
+
+/// The variables of a scope.
+///
+/// Locals are stored in the order they're declared, which is the order the resolver numbered them in,
+/// so they can be looked up by slot. Only the global environment, which has no parent,
+/// looks its variables up by name, since globals can be used before the resolver has seen them.
 #[derive(Debug, Clone)]
-pub(crate) struct Environment {
-    pub values: HashMap<String, Value>,
+pub struct Environment {
+    pub values: Vec<Value>,
+    pub globals: HashMap<String, Value>,
     pub parent: Option<Rc<RefCell<Environment>>>,
 }
 
+impl Default for Environment {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Trace for Environment {
+    fn trace(&self, visit: &mut dyn FnMut(HeapRef)) {
+        for value in self.values.iter().chain(self.globals.values()) {
+            value.trace(visit);
+        }
+        if let Some(parent) = &self.parent {
+            visit(HeapRef::Environment(parent));
+        }
+    }
+}
+
 impl Environment {
     pub fn new() -> Environment {
-        Environment { values: HashMap::new(), parent: None }
+        Environment { values: Vec::new(), globals: HashMap::new(), parent: None }
+    }
+
+    /// Declares the next local variable, which goes in the next slot.
+    pub fn define(&mut self, value: Value) {
+        self.values.push(value);
     }
 
-    pub fn define<'b>(&mut self, name: &'b str, value: Value) {
-        self.values.insert(name.to_string(), value);
+    pub fn define_global(&mut self, name: &str, value: Value) {
+        self.globals.insert(name.to_string(), value);
         // this means you can redine values
         // valid program:
         /*
@@ -40,86 +88,110 @@ impl Environment {
         */
     }
 
-    fn get<'b>(&self, name: &'b str) -> Result<Value, LoxError> {
-        let result = self.values.get(name);
-        match result {
+    pub(crate) fn get_global(&self, name: &str) -> Result<Value, LoxError> {
+        match self.globals.get(name) {
             Some(v) => Ok(v.clone()),
             None => {
-                match &self.parent {
-                    Some(parent) => {
-                        parent.borrow().get(name)
-                    }
-                    None => {
-                        Err(LoxError {kind: LoxErrorKind::NameError, message: "Tried to get a name that is not defined"})
-                    }
-                }   
+                Err(LoxError::new(LoxErrorKind::NameError, format!("Undefined variable '{}'", name)))
             }
         }
     }
 
-    pub fn get_at<'b>(&self, name: &'b str, depth: usize) -> Result<Value, LoxError> {
-        if depth == 0 {
-            return self.get(name);
+    pub(crate) fn assign_global(&mut self, name: &str, value: &Value) -> Result<(), LoxError> {
+        match self.globals.get_mut(name) {
+            Some(v) => {
+                *v = value.clone();
+                Ok(())
+            }
+            None => {
+                Err(LoxError::new(LoxErrorKind::NameError, format!("Can't assign to undefined variable '{}'", name)))
+            }
         }
-        return self.ancestor(depth - 1).borrow().get(name);
     }
 
-    fn ancestor(&self, depth_from_self: usize) -> Rc<RefCell<Environment>> {
-        let mut environment = Rc::clone(self.parent.as_ref().unwrap());
-        let mut current_depth = 0;
-        while current_depth < depth_from_self {
-            // unwrap is valid here because we're assuming that
-            // the resolver has no bugs, so we wouldn't be asked to get some bogus ancestor
-            let ancestor = Rc::clone(environment.borrow().parent.as_ref().unwrap());
-            environment = ancestor;
-            current_depth += 1;
-        }
-        environment
-    }
+    // unwraps and indexing are valid in the *_at functions because we're assuming that
+    // the resolver has no bugs, so we wouldn't be asked for some bogus ancestor or slot
 
-    fn assign<'b>(&mut self, name: &'b str, value: &Value) -> Result<(), LoxError> {
-        if self.values.contains_key(name) {
-            self.values.insert(name.to_string(), value.clone());
-            Ok(())
+    pub fn get_at(&self, local: LocalSlot) -> Value {
+        if local.depth == 0 {
+            self.values[local.slot].clone()
         } else {
-            match &mut self.parent {
-                Some(parent) => {
-                    parent.borrow_mut().assign(name, value)
-                },
-                None => {
-                    Err(LoxError {kind: LoxErrorKind::NameError, message: "Tried to assign to a name that is not defined"})
-                }
-            }
-        } 
+            self.parent.as_ref().unwrap().borrow().get_at(LocalSlot { depth: local.depth - 1, ..local })
+        }
     }
 
-    fn assign_at<'b>(&mut self, depth: usize, name: &'b str, value: &Value) {
-        if depth == 0 {
-            self.values.insert(name.to_string(), value.clone());
+    pub fn assign_at(&mut self, local: LocalSlot, value: &Value) {
+        if local.depth == 0 {
+            self.values[local.slot] = value.clone();
+        } else {
+            self.parent.as_ref().unwrap().borrow_mut().assign_at(LocalSlot { depth: local.depth - 1, ..local }, value);
         }
-        self.ancestor(depth - 1).borrow_mut().values.insert(name.to_string(), value.clone());
     }
 }
 
 impl Display for Environment {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "Keys: {:?}, Has Parent?: {}", self.values.keys(), match self.parent { Some(_) => "yes", None => "no"})
+        write!(f, "Locals: {}, Globals: {:?}, Has Parent?: {}", self.values.len(), self.globals.keys(), match self.parent { Some(_) => "yes", None => "no"})
     }
 }
 
+/// How executing a statement finished.
+///
+/// Anything other than `Normal` is a non-local exit that unwinds enclosing statements
+/// until something handles it: a function call for `Return`, a loop for `Break` and `Continue`.
+#[derive(Debug)]
+pub enum ControlFlow {
+    Normal,
+    Return(Value),
+    Break,
+    Continue,
+}
+
 #[derive(Debug, Clone)]
-pub(crate) enum Value {
+pub enum Value {
     NumberValue(f64),
     StringValue(String),
     BooleanValue(bool),
     NilValue,
     Callable(Box<dyn LoxCallable>),
     InstanceValue(Rc<RefCell<LoxInstance>>),
-    ClassValue(Rc<LoxClass>)
+    ClassValue(Rc<LoxClass>),
+    ListValue(ListRef),
+    MapValue(MapRef),
 }
 
 impl Display for Value {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.fmt_containers(f, &mut Vec::new())
+    }
+}
+
+impl Value {
+    /// The name of the value's type, as used in error messages.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::NumberValue(_) => "number",
+            Value::StringValue(_) => "string",
+            Value::BooleanValue(_) => "boolean",
+            Value::NilValue => "nil",
+            Value::Callable(_) => "function",
+            Value::InstanceValue(_) => "instance",
+            Value::ClassValue(_) => "class",
+            Value::ListValue(_) => "list",
+            Value::MapValue(_) => "map",
+        }
+    }
+
+    /// Formats the value the way it's shown inside a list, ex: strings are quoted.
+    pub fn repr(&self) -> String {
+        match self {
+            Value::StringValue(s) => format!("{:?}", s),
+            _ => self.to_string(),
+        }
+    }
+
+    // `formatting` holds the lists and maps being formatted, so one that contains itself prints as [...] or {...} instead of recursing forever
+    fn fmt_containers(&self, f: &mut std::fmt::Formatter<'_>, formatting: &mut Vec<*const ()>) -> std::fmt::Result {
         match &self {
             Value::NumberValue(n) => write!(f, "{}", n),
             Value::StringValue(n) => write!(f, "{}", n),
@@ -128,38 +200,146 @@ impl Display for Value {
             Value::Callable(c) => write!(f, "{}", c),
             Value::InstanceValue(i) => write!(f, "{}", i.borrow()),
             Value::ClassValue(c) => write!(f, "{}", c),
+            Value::ListValue(l) => {
+                let pointer = Rc::as_ptr(l) as *const ();
+                if formatting.contains(&pointer) {
+                    return write!(f, "[...]");
+                }
+                formatting.push(pointer);
+                write!(f, "[")?;
+                for (i, element) in l.borrow().iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    element.fmt_nested(f, formatting)?;
+                }
+                formatting.pop();
+                write!(f, "]")
+            }
+            Value::MapValue(m) => {
+                let pointer = Rc::as_ptr(m) as *const ();
+                if formatting.contains(&pointer) {
+                    return write!(f, "{{...}}");
+                }
+                formatting.push(pointer);
+                write!(f, "{{")?;
+                for (i, (key, value)) in m.borrow().iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    key.to_value().fmt_nested(f, formatting)?;
+                    write!(f, ": ")?;
+                    value.fmt_nested(f, formatting)?;
+                }
+                formatting.pop();
+                write!(f, "}}")
+            }
+        }
+    }
+
+    // strings inside collections are quoted, so ["1", 1] doesn't print as [1, 1]
+    fn fmt_nested(&self, f: &mut std::fmt::Formatter<'_>, formatting: &mut Vec<*const ()>) -> std::fmt::Result {
+        match self {
+            Value::StringValue(s) => write!(f, "{:?}", s),
+            _ => self.fmt_containers(f, formatting),
         }
     }
 }
 
+// the error for accessing a property on a value that doesn't have any
+pub(crate) fn property_error(name: &str, object: &Value) -> LoxError {
+    LoxError::new(LoxErrorKind::AttributeError, format!("Can't access property '{}' on {}", name, object.type_name()))
+}
+
+// the error for calling something with the wrong number of arguments,
+// ex: "add expected 2 arguments but got 1", or "input expected 0 to 1 arguments but got 2"
+pub(crate) fn arity_error(name: &str, min_arity: usize, arity: usize, got: usize) -> LoxError {
+    let expected = if min_arity == arity { arity.to_string() } else { format!("{} to {}", min_arity, arity) };
+    LoxError::new(LoxErrorKind::TypeError, format!("{} expected {} arguments but got {}", name, expected, got))
+}
+
+// the error for a binary operator applied to operands it doesn't support
+fn operand_error(operator: &BinaryOperator, expected: &str, left: &Value, right: &Value) -> LoxError {
+    LoxError::new(LoxErrorKind::TypeError, format!("Operands to '{}' must be {}, got {} and {}", operator.symbol(), expected, left.type_name(), right.type_name()))
+}
+
+impl Default for TreeWalker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl TreeWalker {
     pub fn new() -> TreeWalker {
-        TreeWalker::new_from_outputter(Outputter::new())
+        TreeWalker::new_from_outputter(Box::new(Printer::new()))
     }
 
-    pub fn new_from_outputter(outputter: Outputter) -> TreeWalker {
-        let environment = Rc::new(RefCell::new(Environment::new()));
+    pub fn new_from_outputter(outputter: Box<dyn OutputSink>) -> TreeWalker {
+        let mut heap = Heap::new();
+        let environment = heap.environment(Environment::new());
         let globals = Rc::clone(&environment);
-        globals.borrow_mut().define("clock", Value::Callable(Box::new(ClockCallable{})));
-        TreeWalker { environment, outputter, locals: HashMap::new(), globals }
+        globals.borrow_mut().define_global("clock", Value::Callable(Box::new(ClockCallable{})));
+        for function in math::natives().into_iter().chain(io::natives()).chain(process::natives()) {
+            globals.borrow_mut().define_global(&function.name(), Value::Callable(Box::new(function)));
+        }
+        globals.borrow_mut().define_global("pi", Value::NumberValue(std::f64::consts::PI));
+        globals.borrow_mut().define_global("from_code", Value::Callable(Box::new(NativeFunction::new("from_code", 1, string::from_code))));
+        let error_class = heap.class(LoxClass::new(String::from("Error"), HashMap::new(), None));
+        TreeWalker { environment, outputter, locals: HashMap::new(), globals, heap, capabilities: Capabilities::default(), args: Vec::new(), call_stack: Vec::new(), vm_stack: vm::Stack::default(), error_class }
     }
 
-    pub fn resolve(&mut self, token: &Token, depth: usize) {
-        self.locals.insert(token.id, depth);
+    // records the calls that were executing when `error` was raised,
+    // unless a call nested deeper already did
+    pub(crate) fn with_backtrace(&self, mut error: LoxError) -> LoxError {
+        if !error.backtrace.is_empty() {
+            return error;
+        }
+        // errors from natives don't have a span yet, they happened at the call itself
+        let mut location = error.span.unwrap_or(self.call_stack.last().unwrap().call_site);
+        for frame in self.call_stack.iter().rev() {
+            error.backtrace.push(StackFrame { function: frame.function.to_owned(), span: location });
+            location = frame.call_site;
+        }
+        error.backtrace.push(StackFrame { function: String::from("<script>"), span: location });
+        error
+    }
+
+    pub fn resolve(&mut self, token: &Token, local: LocalSlot) {
+        self.locals.insert(token.id, local);
     }
 
     fn look_up_variable(&self, token: &Token) -> Result<Value, LoxError> {
-        match self.locals.get(&token.id) {
-            Some(depth) => {
-                self.environment.borrow().get_at(&token.lexeme, *depth)
+        self.get_variable(&token.lexeme, self.locals.get(&token.id).copied())
+    }
+
+    /// Reads a variable from where the resolver found it, or a global called `name` if it didn't.
+    pub(crate) fn get_variable(&self, name: &str, local: Option<LocalSlot>) -> Result<Value, LoxError> {
+        match local {
+            Some(local) => {
+                Ok(self.environment.borrow().get_at(local))
             }
             None => {
-                self.globals.borrow().get(&token.lexeme)
+                self.globals.borrow().get_global(name)
+            }
+        }
+    }
+
+    pub(crate) fn assign_variable(&mut self, name: &str, local: Option<LocalSlot>, value: &Value) -> Result<(), LoxError> {
+        match local {
+            Some(local) => {
+                self.environment.borrow_mut().assign_at(local, value);
+                Ok(())
+            }
+            None => {
+                self.globals.borrow_mut().assign_global(name, value)
             }
         }
     }
     
-    pub fn visit_statement<'b>(&mut self, stmt: &'b Statement) -> Result<(), LoxError> {
+    pub fn visit_statement(&mut self, stmt: &Statement) -> Result<ControlFlow, LoxError> {
+        if self.heap.collection_due() {
+            self.heap.collect();
+        }
         match stmt {
             Statement::PrintStatement(p) => {
                 self.visit_print_statement(p)
@@ -188,21 +368,33 @@ impl TreeWalker {
             Statement::ClassDeclStatement(c) => {
                 self.visit_class_decl_statement(c)
             }
+            Statement::BreakStatement(_) => {
+                Ok(ControlFlow::Break)
+            }
+            Statement::ContinueStatement(_) => {
+                Ok(ControlFlow::Continue)
+            }
+            Statement::TryStatement(t) => {
+                self.visit_try_statement(t)
+            }
+            Statement::ThrowStatement(t) => {
+                self.visit_throw_statement(t)
+            }
         }
     }
 
-    fn visit_print_statement(&mut self, stmt: &PrintStatement) -> Result<(), LoxError> {
+    fn visit_print_statement(&mut self, stmt: &PrintStatement) -> Result<ControlFlow, LoxError> {
         let value = self.visit_expr(&stmt.value)?;
-        self.outputter.output_value(value);
-        Ok(())
+        self.outputter.output_value(&value).map_err(|e| e.with_default_span(stmt.token.span))?;
+        Ok(ControlFlow::Normal)
     }
 
-    fn visit_expression_statement(&mut self, stmt: &ExpressionStatement) -> Result<(), LoxError> {
+    fn visit_expression_statement(&mut self, stmt: &ExpressionStatement) -> Result<ControlFlow, LoxError> {
         self.visit_expr(&stmt.expression)?;
-        Ok(())
+        Ok(ControlFlow::Normal)
     }
 
-    fn visit_var_decl_statement<'b>(&mut self, stmt: &'b VarDeclStatement) -> Result<(), LoxError> {
+    fn visit_var_decl_statement(&mut self, stmt: &VarDeclStatement) -> Result<ControlFlow, LoxError> {
         // if you declare a variable without initializing it, it gets set to nil
         // var x; // x is nil
         let initial_value = match &stmt.initializer {
@@ -210,68 +402,131 @@ impl TreeWalker {
             None => Value::NilValue
         };
         self.define(&stmt.token.lexeme, initial_value);
-        Ok(())
+        Ok(ControlFlow::Normal)
+    }
+
+    fn visit_block_statement(&mut self, stmt: &BlockStatement) -> Result<ControlFlow, LoxError> {
+        let env = self.child_environment();
+        self.execute_block(&stmt.statements, env)
+    }
+
+    fn visit_try_statement(&mut self, stmt: &TryStatement) -> Result<ControlFlow, LoxError> {
+        let env = self.child_environment();
+        let mut result = self.execute_block(&stmt.body, env);
+        if let Some(catch) = &stmt.catch {
+            result = match result {
+                Err(error) if error.is_catchable() => {
+                    let mut env = Environment::new();
+                    env.parent = Some(Rc::clone(&self.environment));
+                    env.define(self.exception_value(error));
+                    let env = self.heap.environment(env);
+                    self.execute_block(&catch.body, env)
+                }
+                other => other,
+            };
+        }
+        if let Some(finally) = &stmt.finally {
+            // the finally block always runs, and if it exits early or fails
+            // that replaces whatever the try and catch blocks did
+            let env = self.child_environment();
+            match self.execute_block(finally, env)? {
+                ControlFlow::Normal => {},
+                exit => return Ok(exit),
+            }
+        }
+        result
     }
 
-    fn visit_block_statement<'b>(&mut self, stmt: &'b BlockStatement) -> Result<(), LoxError> {
+    fn visit_throw_statement(&mut self, stmt: &ThrowStatement) -> Result<ControlFlow, LoxError> {
+        let value = self.visit_expr(&stmt.value)?;
+        Err(LoxError::new(LoxErrorKind::Thrown(Box::new(value.clone())), value.to_string()).with_span(stmt.keyword.span.to(stmt.value.span())))
+    }
+
+    // the value a catch clause receives for `error`: thrown values are caught as they are,
+    // errors raised by the interpreter become an `Error` instance describing them
+    pub(crate) fn exception_value(&mut self, error: LoxError) -> Value {
+        match error.kind {
+            LoxErrorKind::Thrown(value) => *value,
+            kind => {
+                let mut instance = LoxInstance::new(Rc::clone(&self.error_class));
+                instance.set("kind", Value::StringValue(kind.to_string()));
+                instance.set("message", Value::StringValue(error.message));
+                let (line, column) = match error.span {
+                    Some(span) => (Value::NumberValue(span.line as f64), Value::NumberValue(span.column as f64)),
+                    None => (Value::NilValue, Value::NilValue),
+                };
+                instance.set("line", line);
+                instance.set("column", column);
+                Value::InstanceValue(self.heap.instance(instance))
+            }
+        }
+    }
+
+    pub(crate) fn child_environment(&mut self) -> Rc<RefCell<Environment>> {
         let mut env = Environment::new();
         env.parent = Some(Rc::clone(&self.environment));
-        self.execute_block(&stmt.statements, Rc::new(RefCell::new(env)))
+        self.heap.environment(env)
     }
 
-    fn visit_if_statement<'b>(&mut self, stmt: &'b IfStatement) -> Result<(), LoxError> {
+    fn visit_if_statement(&mut self, stmt: &IfStatement) -> Result<ControlFlow, LoxError> {
         let condition = self.visit_expr(&stmt.condition)?;
         if self.is_truthy(&condition) {
             self.visit_statement(stmt.then_branch.as_ref())
         } else if let Some(else_branch) = &stmt.else_branch{
             self.visit_statement(else_branch.as_ref())
         } else {
-            Ok(())
+            Ok(ControlFlow::Normal)
         }
     }
 
-    fn visit_while_statement<'b>(&mut self, stmt: &'b WhileStatement) -> Result<(), LoxError> {
+    fn visit_while_statement(&mut self, stmt: &WhileStatement) -> Result<ControlFlow, LoxError> {
         loop {
             let condition = self.visit_expr(&stmt.condition)?;
             if !self.is_truthy(&condition) {
                 break;
             }
-            self.visit_statement(stmt.body.as_ref())?;
+            match self.visit_statement(stmt.body.as_ref())? {
+                ControlFlow::Break => break,
+                ControlFlow::Normal | ControlFlow::Continue => {},
+                // not ours to handle, keep unwinding
+                flow @ ControlFlow::Return(_) => return Ok(flow),
+            }
+            if let Some(increment) = &stmt.increment {
+                self.visit_expr(increment)?;
+            }
         }
-        Ok(())
+        Ok(ControlFlow::Normal)
     }
 
-    fn visit_fun_decl_statement<'b>(&mut self, stmt: &'b FunDeclStatement) -> Result<(), LoxError> {
-        let fun = Function::new(stmt.to_owned(), Rc::clone(&self.environment), false);
+    fn visit_fun_decl_statement(&mut self, stmt: &Rc<FunDeclStatement>) -> Result<ControlFlow, LoxError> {
+        let fun = Function::new(Rc::clone(stmt), Rc::clone(&self.environment), false);
         self.define(&stmt.name.lexeme, Value::Callable(Box::new(fun)));
-        Ok(())
+        Ok(ControlFlow::Normal)
     }
 
-    fn visit_return_statement<'b>(&mut self, stmt: &'b ReturnStatement) -> Result<(), LoxError> {
+    fn visit_return_statement(&mut self, stmt: &ReturnStatement) -> Result<ControlFlow, LoxError> {
         match &stmt.value {
             Some(expr) => {
-                let value = self.visit_expr(&expr)?;
-                Err(LoxError {kind: LoxErrorKind::Return(value), message: ""})
+                let value = self.visit_expr(expr)?;
+                Ok(ControlFlow::Return(value))
             },
             _ => {
-                Err(LoxError {kind: LoxErrorKind::Return(Value::NilValue), message: ""})
+                Ok(ControlFlow::Return(Value::NilValue))
             }
         }
     }
 
-    fn visit_class_decl_statement<'b>(&mut self, stmt: &'b ClassDeclStatement) -> Result<(), LoxError> {
+    fn visit_class_decl_statement(&mut self, stmt: &ClassDeclStatement) -> Result<ControlFlow, LoxError> {
         let superclass;
         if let Some(superclass_var) = &stmt.superclass {
             match self.visit_variable(superclass_var)? {
                 Value::ClassValue(c) => { 
                     superclass = Some(c.clone());
-                    let mut env = Environment::new();
-                    env.parent = Some(Rc::clone(&self.environment));
-                    self.environment = Rc::new(RefCell::new(env));
-                    self.environment.borrow_mut().define("super", Value::ClassValue(c))
+                    self.environment = self.child_environment();
+                    self.environment.borrow_mut().define(Value::ClassValue(c))
                 },
-                _ => {
-                    return Err(LoxError {kind: LoxErrorKind::TypeError, message: "Superclass must be a class"})
+                other => {
+                    return Err(LoxError::new(LoxErrorKind::TypeError, format!("Superclass must be a class, got {}", other.type_name())).with_span(superclass_var.token.span))
                 }
             };
         } else {
@@ -283,7 +538,7 @@ impl TreeWalker {
         let mut methods: HashMap<String, Function> = HashMap::new();
         for method in &stmt.methods {
             let is_initializer = method.name.lexeme == "init";
-            let callable = Function::new(method.clone(), Rc::clone(&self.environment), is_initializer);
+            let callable = Function::new(Rc::clone(method), Rc::clone(&self.environment), is_initializer);
             methods.insert(method.name.lexeme.clone(), callable);
         }
 
@@ -293,13 +548,16 @@ impl TreeWalker {
             let previous = self.environment.borrow().parent.as_ref().unwrap().to_owned();
             self.environment = previous;
         }
-        self.define(&stmt.name.lexeme, Value::ClassValue(Rc::new(class)));
+        let class = self.heap.class(class);
+        self.define(&stmt.name.lexeme, Value::ClassValue(class));
 
-        Ok(())
+        Ok(ControlFlow::Normal)
     }
 
-    fn visit_expr(&mut self, expr: &Expr) -> Result<Value, LoxError> {
-        match expr {
+    pub fn visit_expr(&mut self, expr: &Expr) -> Result<Value, LoxError> {
+        // errors raised while evaluating an expression point at it,
+        // unless a nested expression already claimed them
+        let result = match expr {
             Expr::Binary(e) => {
                 self.visit_binary(e)
             }
@@ -336,13 +594,50 @@ impl TreeWalker {
             Expr::Super(s) => {
                 self.visit_super(s)
             }
+            Expr::List(l) => {
+                self.visit_list(l)
+            }
+            Expr::Map(m) => {
+                self.visit_map(m)
+            }
+            Expr::Index(i) => {
+                self.visit_index(i)
+            }
+            Expr::IndexSet(i) => {
+                self.visit_index_set(i)
+            }
+            Expr::Lambda(l) => {
+                self.visit_lambda(l)
+            }
+            Expr::Interpolation(i) => {
+                self.visit_interpolation(i)
+            }
+        };
+        result.map_err(|e| e.with_default_span(expr.span()))
+    }
+
+    fn visit_lambda(&mut self, expr: &Lambda) -> Result<Value, LoxError> {
+        let fun = Function::new(Rc::clone(&expr.declaration), Rc::clone(&self.environment), false);
+        Ok(Value::Callable(Box::new(fun)))
+    }
+
+    fn visit_interpolation(&mut self, expr: &Interpolation) -> Result<Value, LoxError> {
+        let mut string = String::new();
+        for part in &expr.parts {
+            string.push_str(&self.visit_expr(part)?.to_string());
         }
+        Ok(Value::StringValue(string))
     }
 
     fn visit_binary(&mut self, expr: &Binary) -> Result<Value, LoxError> {
         let left = self.visit_expr(expr.left.as_ref())?;
         let right = self.visit_expr(expr.right.as_ref())?;
-        match expr.operator {
+        self.binary(&expr.operator, left, right)
+    }
+
+    /// Applies a binary operator to operands that have already been evaluated.
+    pub(crate) fn binary(&self, operator: &BinaryOperator, left: Value, right: Value) -> Result<Value, LoxError> {
+        match operator {
             BinaryOperator::BangEqual => {
                 Ok(Value::BooleanValue(!self.is_equal(&left, &right)))
             }
@@ -354,7 +649,7 @@ impl TreeWalker {
                     (Value::NumberValue(l), Value::NumberValue(r)) => {
                         Ok(Value::BooleanValue(l > r))
                     }
-                    _ => Err(LoxError {kind: LoxErrorKind::TypeError, message: "unsupported operand types"})
+                    (l, r) => Err(operand_error(operator, "numbers", &l, &r))
                 }
             }
             BinaryOperator::GreaterEqual => {
@@ -362,7 +657,7 @@ impl TreeWalker {
                     (Value::NumberValue(l), Value::NumberValue(r)) => {
                         Ok(Value::BooleanValue(l >= r))
                     }
-                    _ => Err(LoxError {kind: LoxErrorKind::TypeError, message: "unsupported operand types"})
+                    (l, r) => Err(operand_error(operator, "numbers", &l, &r))
                 }
             }
             BinaryOperator::Less => {
@@ -370,7 +665,7 @@ impl TreeWalker {
                     (Value::NumberValue(l), Value::NumberValue(r)) => {
                         Ok(Value::BooleanValue(l < r))
                     }
-                    _ => Err(LoxError {kind: LoxErrorKind::TypeError, message: "unsupported operand types"})
+                    (l, r) => Err(operand_error(operator, "numbers", &l, &r))
                 }
             }
             BinaryOperator::LessEqual => {
@@ -378,7 +673,7 @@ impl TreeWalker {
                     (Value::NumberValue(l), Value::NumberValue(r)) => {
                         Ok(Value::BooleanValue(l <= r))
                     }
-                    _ => Err(LoxError {kind: LoxErrorKind::TypeError, message: "unsupported operand types"})
+                    (l, r) => Err(operand_error(operator, "numbers", &l, &r))
                 }
             }
             BinaryOperator::Minus => {
@@ -386,7 +681,7 @@ impl TreeWalker {
                     (Value::NumberValue(l), Value::NumberValue(r)) => {
                         Ok(Value::NumberValue(l - r))
                     }
-                    _ => Err(LoxError {kind: LoxErrorKind::TypeError, message: "unsupported operand types"})
+                    (l, r) => Err(operand_error(operator, "numbers", &l, &r))
                 }
             }
             BinaryOperator::Plus => {
@@ -397,7 +692,7 @@ impl TreeWalker {
                     (Value::StringValue(l), Value::StringValue(r)) => {
                         Ok(Value::StringValue(format!("{}{}", l, r)))
                     }
-                    _ => Err(LoxError {kind: LoxErrorKind::TypeError, message: "unsupported operand types"})
+                    (l, r) => Err(operand_error(operator, "two numbers or two strings", &l, &r))
                 }
             },
             BinaryOperator::Slash => {
@@ -405,7 +700,7 @@ impl TreeWalker {
                     (Value::NumberValue(l), Value::NumberValue(r)) => {
                         Ok(Value::NumberValue(l / r))
                     }
-                    _ => Err(LoxError {kind: LoxErrorKind::TypeError, message: "unsupported operand types"})
+                    (l, r) => Err(operand_error(operator, "numbers", &l, &r))
                 }
             }
             BinaryOperator::Star => {
@@ -413,7 +708,24 @@ impl TreeWalker {
                     (Value::NumberValue(l), Value::NumberValue(r)) => {
                         Ok(Value::NumberValue(l * r))
                     }
-                    _ => Err(LoxError {kind: LoxErrorKind::TypeError, message: "unsupported operand types"})
+                    (l, r) => Err(operand_error(operator, "numbers", &l, &r))
+                }
+            },
+            BinaryOperator::Percent => {
+                match (left, right) {
+                    // the remainder has the sign of the left operand, ex: -7 % 3 == -1
+                    (Value::NumberValue(l), Value::NumberValue(r)) => {
+                        Ok(Value::NumberValue(l % r))
+                    }
+                    (l, r) => Err(operand_error(operator, "numbers", &l, &r))
+                }
+            },
+            BinaryOperator::StarStar => {
+                match (left, right) {
+                    (Value::NumberValue(l), Value::NumberValue(r)) => {
+                        Ok(Value::NumberValue(l.powf(r)))
+                    }
+                    (l, r) => Err(operand_error(operator, "numbers", &l, &r))
                 }
             },
         }
@@ -421,14 +733,19 @@ impl TreeWalker {
 
     fn visit_unary(&mut self, expr: &Unary) -> Result<Value, LoxError> {
         let right = self.visit_expr(expr.right.as_ref())?;
-        match &expr.operator {
+        self.unary(&expr.operator, right)
+    }
+
+    /// Applies a unary operator to an operand that has already been evaluated.
+    pub(crate) fn unary(&self, operator: &UnaryOperator, right: Value) -> Result<Value, LoxError> {
+        match operator {
             UnaryOperator::Bang => {
                 Ok(Value::BooleanValue(self.is_truthy(&right)))
             },
             UnaryOperator::Minus => {
                 match right {
-                    Value::NumberValue(n) => Ok(Value::NumberValue(n * -1.0)),
-                    _ => Err(LoxError {kind: LoxErrorKind::TypeError, message: "unsupported operant types"})
+                    Value::NumberValue(n) => Ok(Value::NumberValue(-n)),
+                    other => Err(LoxError::new(LoxErrorKind::TypeError, format!("Operand to '{}' must be a number, got {}", operator.symbol(), other.type_name())))
                 }
             }
         }
@@ -449,14 +766,7 @@ impl TreeWalker {
 
     fn visit_assignment(&mut self, expr: &Assignment) -> Result<Value, LoxError> {
         let value = self.visit_expr(expr.value.as_ref())?;
-        match self.locals.get(&expr.token.id) {
-            Some(depth) => {
-                self.environment.borrow_mut().assign_at(*depth, &expr.token.lexeme, &value);
-            }
-            None => {
-                self.globals.borrow_mut().assign(&expr.token.lexeme, &value)?;
-            }
-        };
+        self.assign_variable(&expr.token.lexeme, self.locals.get(&expr.token.id).copied(), &value)?;
         Ok(value)
     }
 
@@ -475,7 +785,7 @@ impl TreeWalker {
             }
         };
         let right_value = self.visit_expr(expr.right.as_ref())?;
-        return Ok(Value::BooleanValue(self.is_truthy(&right_value)));
+        Ok(Value::BooleanValue(self.is_truthy(&right_value)))
     }
 
     fn visit_call(&mut self, expr: &Call) -> Result<Value, LoxError> {
@@ -483,36 +793,62 @@ impl TreeWalker {
         let mut args = Vec::new();
         // argument expressions evaluated from left to right
         for arg in &expr.arguments {
-            args.push(self.visit_expr(&arg)?)
+            args.push(self.visit_expr(arg)?)
         }
+        self.call_value(callee, args, expr.callee.span().to(expr.token.span))
+    }
+
+    /// Calls a function or class with arguments that have already been evaluated.
+    /// `call_site` is the call expression, which is recorded in backtraces.
+    pub(crate) fn call_value(&mut self, callee: Value, args: Vec<Value>, call_site: Span) -> Result<Value, LoxError> {
         match callee {
             Value::Callable(callee) => {
-                if args.len() != callee.arity() {
-                    Err(LoxError {kind: LoxErrorKind::TypeError, message: "Got wrong number of arguments"})
+                if args.len() < callee.min_arity() || args.len() > callee.arity() {
+                    Err(arity_error(&callee.name(), callee.min_arity(), callee.arity(), args.len()))
                 } else {
-                    callee.call(self, args)
+                    self.call_stack.push(CallFrame { function: callee.name(), call_site });
+                    let result = callee.call(self, args).map_err(|e| self.with_backtrace(e));
+                    self.call_stack.pop();
+                    result
                 }
             },
             Value::ClassValue(class) => {
                 if args.len() != class.arity() {
-                    Err(LoxError {kind: LoxErrorKind::TypeError, message: "Got wrong number of arguments"})
+                    Err(arity_error(&class.name(), class.arity(), class.arity(), args.len()))
                 } else {
-                    class.call(self, args)
+                    self.call_stack.push(CallFrame { function: class.name(), call_site });
+                    let result = class.call(self, args).map_err(|e| self.with_backtrace(e));
+                    self.call_stack.pop();
+                    result
                 }
             }
-            _ => {
-                Err(LoxError {kind: LoxErrorKind::TypeError, message: "expression is not callable"})
+            other => {
+                Err(LoxError::new(LoxErrorKind::TypeError, format!("Can only call functions and classes, got {}", other.type_name())))
             }
         }
     }
 
     fn visit_get(&mut self, expr: &Get) -> Result<Value, LoxError> {
-        match self.visit_expr(expr.object.as_ref())? {
+        let object = self.visit_expr(expr.object.as_ref())?;
+        self.get_property(object, &expr.name.lexeme)
+    }
+
+    pub(crate) fn get_property(&mut self, object: Value, name: &str) -> Result<Value, LoxError> {
+        match object {
             Value::InstanceValue(i) => {
-                i.as_ref().borrow().get(&expr.name.lexeme, &i)
+                i.as_ref().borrow().get(name, &i, &mut self.heap)
             },
-            _ => {
-                Err(LoxError {kind: LoxErrorKind::AttributeError, message: "only instances have attributes"})
+            Value::ListValue(l) => {
+                list::get_method(&l, name)
+            },
+            Value::MapValue(m) => {
+                map::get_method(&m, name)
+            },
+            Value::StringValue(s) => {
+                string::get_method(&s, name)
+            },
+            other => {
+                Err(property_error(name, &other))
             }
         }
     }
@@ -527,8 +863,8 @@ impl TreeWalker {
                 i.as_ref().borrow_mut().set(&expr.name.lexeme, value.clone());
                 Ok(value)
             },
-            _ => {
-                Err(LoxError {kind: LoxErrorKind::AttributeError, message: "only instances have attributes"})
+            other => {
+                Err(property_error(&expr.name.lexeme, &other))
             }
         }
     }
@@ -539,57 +875,147 @@ impl TreeWalker {
 
     fn visit_super(&mut self, expr: &Super) -> Result<Value, LoxError> {
         // we can unwrap since we know the resolver set up "super" correctly
-        let distance = self.locals.get(&expr.keyword.id).unwrap();
-        let superclass = self.environment.borrow().get_at("super", *distance)?;
+        let local = *self.locals.get(&expr.keyword.id).unwrap();
+        self.super_method(&expr.method.lexeme, local)
+    }
+
+    /// Looks up `super.method`, where `local` is where the resolver found "super".
+    pub(crate) fn super_method(&mut self, method: &str, local: LocalSlot) -> Result<Value, LoxError> {
+        let superclass = self.environment.borrow().get_at(local);
+        // we know "this" is one scope closer than "super", and the only variable in it,
+        // due to the way we wrote visit_class_decl_statement
+        let instance = self.environment.borrow().get_at(LocalSlot { depth: local.depth - 1, slot: 0 });
+        self.bind_super_method(superclass, instance, method)
+    }
+
+    /// Finds `method` on `superclass` and binds it to `instance`, the "this" of the method using "super".
+    pub(crate) fn bind_super_method(&mut self, superclass: Value, instance: Value, method: &str) -> Result<Value, LoxError> {
         let superclass = match superclass {
             Value::ClassValue(c) => c,
             _ => {
                 // should never occur
-                return Err(LoxError {kind: LoxErrorKind::TypeError, message: "expect super to be a class"})
+                return Err(LoxError::new(LoxErrorKind::TypeError, "expect super to be a class"))
             }
         };
-        // we know "this" is one scope closer than "super" due to the way we wrote
-        // visit_class_decl_statement
-        let instance = self.environment.borrow().get_at("this", *distance - 1)?;
         let instance = match instance {
             Value::InstanceValue(i) => i,
             _ => {
                 // should never occur
-                return Err(LoxError {kind: LoxErrorKind::RuntimeError, message: "error calling super method"});
+                return Err(LoxError::new(LoxErrorKind::RuntimeError, "error calling super method"));
             },
         };
-        let method = superclass.find_method(&expr.method.lexeme);
-        match method {
+        match superclass.find_method(method) {
             Some(method) => {
-                Ok(Value::Callable(Box::new(method.bind(&instance))))
+                Ok(Value::Callable(Box::new(method.bind(&instance, &mut self.heap))))
             }
             None => {
-                Err(LoxError {kind: LoxErrorKind::AttributeError, message: "super class has method with that name"})
+                Err(LoxError::new(LoxErrorKind::AttributeError, format!("Superclass '{}' has no method '{}'", superclass.name, method)))
+            }
+        }
+    }
+
+    fn visit_list(&mut self, expr: &List) -> Result<Value, LoxError> {
+        let mut elements = Vec::new();
+        for element in &expr.elements {
+            elements.push(self.visit_expr(element)?);
+        }
+        Ok(Value::ListValue(self.heap.list(elements)))
+    }
+
+    fn visit_map(&mut self, expr: &Map) -> Result<Value, LoxError> {
+        let mut entries = LoxMap::new();
+        for (key, value) in &expr.entries {
+            let key = MapKey::from_value(&self.visit_expr(key)?)?;
+            let value = self.visit_expr(value)?;
+            entries.insert(key, value);
+        }
+        Ok(Value::MapValue(self.heap.map(entries)))
+    }
+
+    fn visit_index(&mut self, expr: &Index) -> Result<Value, LoxError> {
+        let object = self.visit_expr(expr.object.as_ref())?;
+        let index = self.visit_expr(expr.index.as_ref())?;
+        self.index(object, index)
+    }
+
+    pub(crate) fn index(&self, object: Value, index: Value) -> Result<Value, LoxError> {
+        match object {
+            Value::ListValue(l) => {
+                let l = l.borrow();
+                let index = list::list_index(&index, l.len())?;
+                Ok(l[index].clone())
+            },
+            Value::MapValue(m) => {
+                let key = MapKey::from_value(&index)?;
+                match m.borrow().get(&key) {
+                    Some(value) => Ok(value.clone()),
+                    None => Err(LoxError::new(LoxErrorKind::KeyError, format!("Map has no entry with key {}", index.repr())))
+                }
+            },
+            Value::StringValue(s) => {
+                string::char_at(&s, &index)
+            },
+            other => {
+                Err(LoxError::new(LoxErrorKind::TypeError, format!("Only lists, maps and strings can be indexed, got {}", other.type_name())))
+            }
+        }
+    }
+
+    fn visit_index_set(&mut self, expr: &IndexSet) -> Result<Value, LoxError> {
+        let object = self.visit_expr(expr.object.as_ref())?;
+        let index = self.visit_expr(expr.index.as_ref())?;
+        let value = self.visit_expr(expr.value.as_ref())?;
+        self.index_set(object, index, value)
+    }
+
+    pub(crate) fn index_set(&self, object: Value, index: Value, value: Value) -> Result<Value, LoxError> {
+        match object {
+            Value::ListValue(l) => {
+                let mut l = l.borrow_mut();
+                let index = list::list_index(&index, l.len())?;
+                l[index] = value.clone();
+                Ok(value)
+            },
+            Value::MapValue(m) => {
+                let key = MapKey::from_value(&index)?;
+                m.borrow_mut().insert(key, value.clone());
+                Ok(value)
+            },
+            other => {
+                Err(LoxError::new(LoxErrorKind::TypeError, format!("Only list and map elements can be assigned to, got {}", other.type_name())))
             }
         }
     }
 
-    pub fn execute_block(&mut self, statements: &Vec<Statement>, env: Rc<RefCell<Environment>>) -> Result<(), LoxError> {
+    pub fn execute_block(&mut self, statements: &Vec<Statement>, env: Rc<RefCell<Environment>>) -> Result<ControlFlow, LoxError> {
         let previous_env = Rc::clone(&self.environment);
         self.environment = env;
         for statement in statements {
-            let result = self.visit_statement(statement);
-            if let Err(e) = result {
-                // clean up on error
-                self.environment = previous_env;
-                return Err(e)
+            match self.visit_statement(statement) {
+                Ok(ControlFlow::Normal) => {},
+                // errors and non-local exits both stop the block early
+                result => {
+                    // clean up
+                    self.environment = previous_env;
+                    return result
+                }
             }
         }
         // clean up
         self.environment = previous_env;
-        Ok(())
+        Ok(ControlFlow::Normal)
     }
 
-    fn define<'b>(&mut self, name: &'b str, value: Value) {
-        self.environment.borrow_mut().define(name, value)
+    /// Declares a variable in the current environment, which is a global one at the top level of a script.
+    pub(crate) fn define(&mut self, name: &str, value: Value) {
+        if Rc::ptr_eq(&self.environment, &self.globals) {
+            self.globals.borrow_mut().define_global(name, value)
+        } else {
+            self.environment.borrow_mut().define(value)
+        }
     }
 
-    fn is_equal(&self, left: &Value, right: &Value) -> bool {
+    pub(crate) fn is_equal(&self, left: &Value, right: &Value) -> bool {
         match (left, right) {
             (Value::NumberValue(l), Value::NumberValue(r)) => {
                l == r
@@ -602,12 +1028,18 @@ impl TreeWalker {
             },
             (Value::NilValue, Value::NilValue) => {
                true
+            },
+            (Value::ListValue(l), Value::ListValue(r)) => {
+               Rc::ptr_eq(l, r)
+            },
+            (Value::MapValue(l), Value::MapValue(r)) => {
+               Rc::ptr_eq(l, r)
             }
             _ => false
         }
     }
 
-    fn is_truthy(&self, val: &Value) -> bool {
+    pub(crate) fn is_truthy(&self, val: &Value) -> bool {
         // false and nil are falsey, everything else is truthy
         match val {
             Value::BooleanValue(b) => b.to_owned(),
@@ -617,6 +1049,8 @@ impl TreeWalker {
             Value::Callable(_) => true,
             Value::InstanceValue(_) => true,
             Value::ClassValue(_) => true,
+            Value::ListValue(_) => true,
+            Value::MapValue(_) => true,
         }
     }
 }
\ No newline at end of file
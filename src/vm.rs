@@ -0,0 +1,584 @@
+//! A stack machine that runs bytecode from `compiler::Compiler`.
+//!
+//! Locals live in call frames on the VM's value stack, and calls from one compiled function
+//! to another stay inside the VM loop. The rest of the runtime is shared with `TreeWalker`:
+//! globals, the heap, output and the call stack used for backtraces all live there,
+//! so functions and classes declared by either backend can be used from the other.
+
+use std::{cell::RefCell, collections::HashMap, mem, rc::Rc};
+
+use crate::{callable::{Function, LoxCallable}, chunk::{Capture, Chunk, FunctionProto, Op, TryProto}, class::{LoxClass, LoxInstance}, error::{LoxError, LoxErrorKind}, gc::{HeapRef, Trace}, map::{LoxMap, MapKey}, tokens::Span, tree_walker::{self, CallFrame, ControlFlow, TreeWalker, Value}};
+
+/// A variable captured by a closure. It points at the variable's slot on the stack while
+/// the variable is in scope, and holds the value itself once the scope has ended.
+#[derive(Debug)]
+pub enum Upvalue {
+    Open(usize),
+    Closed(Value),
+}
+
+pub type UpvalueRef = Rc<RefCell<Upvalue>>;
+
+impl Trace for Upvalue {
+    fn trace(&self, visit: &mut dyn FnMut(HeapRef)) {
+        if let Upvalue::Closed(value) = self {
+            value.trace(visit);
+        }
+    }
+}
+
+/// The VM's value stack, along with the upvalues that still point into it.
+///
+/// This lives in `TreeWalker` rather than `Vm`, so that the VM started when something outside of it
+/// (ex: the tree-walker or a native) calls a compiled function shares it with the VM that created that function.
+#[derive(Debug, Default)]
+pub struct Stack {
+    values: Vec<Value>,
+    open_upvalues: Vec<UpvalueRef>,
+}
+
+pub struct Vm<'a> {
+    runtime: &'a mut TreeWalker,
+    frames: Vec<Frame>,
+}
+
+// a function call, script or try block that's running
+struct Frame {
+    chunk: Rc<Chunk>,
+    ip: usize, // where the frame carries on, once a call it made returns
+    base: usize, // where its slot 0 is on the stack
+    function: Option<Function>, // None for scripts
+    is_call: bool, // pushed by `Op::Call`, which also pushed the runtime's call stack
+}
+
+// what to do after an instruction
+enum Step {
+    Next,
+    Jump(usize),
+    Enter, // a call pushed a new frame
+    Exit(ControlFlow),
+}
+
+impl<'a> Vm<'a> {
+    pub fn new(runtime: &'a mut TreeWalker) -> Vm<'a> {
+        Vm { runtime, frames: Vec::new() }
+    }
+
+    /// Runs a compiled script or expression. Its locals are dropped once it finishes.
+    pub fn execute(&mut self, chunk: Rc<Chunk>) -> Result<ControlFlow, LoxError> {
+        let base = self.runtime.vm_stack.values.len();
+        // scripts don't use slot 0, it's there so every frame is laid out the same way
+        self.push(Value::NilValue);
+        self.frames.push(Frame { chunk, ip: 0, base, function: None, is_call: false });
+        let result = self.run();
+        self.unwind(base);
+        result
+    }
+
+    /// Calls a compiled function from outside of the VM, ex: from the tree-walker or a native.
+    pub(crate) fn call(&mut self, function: &Function, arguments: Vec<Value>) -> Result<Value, LoxError> {
+        // only called for functions that have code
+        let compiled = function.code().unwrap();
+        let base = self.runtime.vm_stack.values.len();
+        self.push(compiled.this.as_ref().map_or(Value::NilValue, |this| Value::InstanceValue(Rc::clone(this))));
+        self.runtime.vm_stack.values.extend(arguments);
+        self.frames.push(Frame { chunk: Rc::clone(&compiled.code), ip: 0, base, function: Some(function.clone()), is_call: false });
+        let result = match self.run() {
+            Ok(flow) => Ok(self.return_value(Some(function), base, flow)),
+            Err(error) => Err(error),
+        };
+        self.unwind(base);
+        result
+    }
+
+    // runs the frame on top until it finishes, along with any calls it makes
+    fn run(&mut self) -> Result<ControlFlow, LoxError> {
+        let depth = self.frames.len();
+        let mut chunk = Rc::clone(&self.frames[depth - 1].chunk);
+        let mut ip = 0;
+        loop {
+            if self.runtime.heap.collection_due() {
+                self.runtime.heap.collect();
+            }
+            let step = if ip < chunk.code.len() { self.step(&chunk, ip) } else { Ok(Step::Exit(ControlFlow::Normal)) };
+            match step {
+                Ok(Step::Next) => ip += 1,
+                Ok(Step::Jump(target)) => ip = target,
+                Ok(Step::Enter) => {
+                    let callee = self.frames.len() - 1;
+                    self.frames[callee - 1].ip = ip + 1;
+                    chunk = Rc::clone(&self.frames[callee].chunk);
+                    ip = 0;
+                }
+                Ok(Step::Exit(flow)) => {
+                    let frame = self.frames.pop().unwrap();
+                    if self.frames.len() < depth {
+                        return Ok(flow);
+                    }
+                    // a call made during this run returned, its caller gets the result
+                    let value = self.return_value(frame.function.as_ref(), frame.base, flow);
+                    self.runtime.call_stack.pop();
+                    self.unwind(frame.base);
+                    self.push(value);
+                    let caller = self.frames.last().unwrap();
+                    chunk = Rc::clone(&caller.chunk);
+                    ip = caller.ip;
+                }
+                Err(error) => {
+                    let mut error = error.with_default_span(chunk.spans[ip]);
+                    if !self.runtime.call_stack.is_empty() {
+                        error = self.runtime.with_backtrace(error);
+                    }
+                    // whoever started this run drops what's left on the stack
+                    while self.frames.len() >= depth {
+                        if self.frames.pop().unwrap().is_call {
+                            self.runtime.call_stack.pop();
+                        }
+                    }
+                    return Err(error);
+                }
+            }
+        }
+    }
+
+    fn step(&mut self, chunk: &Chunk, ip: usize) -> Result<Step, LoxError> {
+        match &chunk.code[ip] {
+            Op::Constant(index) => self.push(chunk.constants[*index as usize].clone()),
+            Op::Nil => self.push(Value::NilValue),
+            Op::True => self.push(Value::BooleanValue(true)),
+            Op::False => self.push(Value::BooleanValue(false)),
+            Op::Pop => {
+                self.pop();
+            }
+            Op::GetLocal(slot) => {
+                let value = self.runtime.vm_stack.values[self.base() + *slot as usize].clone();
+                self.push(value);
+            }
+            Op::SetLocal(slot) => {
+                let slot = self.base() + *slot as usize;
+                self.runtime.vm_stack.values[slot] = self.peek();
+            }
+            Op::GetUpvalue(index) => {
+                let value = match &*self.upvalue(*index).borrow() {
+                    Upvalue::Open(slot) => self.runtime.vm_stack.values[*slot].clone(),
+                    Upvalue::Closed(value) => value.clone(),
+                };
+                self.push(value);
+            }
+            Op::SetUpvalue(index) => {
+                let value = self.peek();
+                let upvalue = Rc::clone(self.upvalue(*index));
+                match &mut *upvalue.borrow_mut() {
+                    Upvalue::Open(slot) => self.runtime.vm_stack.values[*slot] = value,
+                    Upvalue::Closed(closed) => *closed = value,
+                };
+            }
+            Op::GetGlobal(name) => {
+                let value = self.runtime.globals.borrow().get_global(&chunk.names[*name as usize])?;
+                self.push(value);
+            }
+            Op::SetGlobal(name) => {
+                let value = self.peek();
+                self.runtime.globals.borrow_mut().assign_global(&chunk.names[*name as usize], &value)?;
+            }
+            Op::DefineGlobal(name) => {
+                let value = self.pop();
+                self.runtime.globals.borrow_mut().define_global(&chunk.names[*name as usize], value);
+            }
+            Op::Unwind(locals) => {
+                let height = self.base() + *locals as usize;
+                self.unwind(height);
+            }
+            Op::GetProperty(name) => {
+                let object = self.pop();
+                let value = self.runtime.get_property(object, &chunk.names[*name as usize])?;
+                self.push(value);
+            }
+            Op::SetProperty(name) => {
+                let value = self.pop();
+                match self.pop() {
+                    Value::InstanceValue(i) => i.borrow_mut().set(&chunk.names[*name as usize], value.clone()),
+                    other => return Err(tree_walker::property_error(&chunk.names[*name as usize], &other)),
+                }
+                self.push(value);
+            }
+            Op::GetSuper(method) => {
+                let superclass = self.pop();
+                let instance = self.pop();
+                let value = self.runtime.bind_super_method(superclass, instance, &chunk.names[*method as usize])?;
+                self.push(value);
+            }
+            Op::Index => {
+                let index = self.pop();
+                let object = self.pop();
+                let value = self.runtime.index(object, index)?;
+                self.push(value);
+            }
+            Op::IndexSet => {
+                let value = self.pop();
+                let index = self.pop();
+                let object = self.pop();
+                let value = self.runtime.index_set(object, index, value)?;
+                self.push(value);
+            }
+            Op::Binary(operator) => {
+                let right = self.pop();
+                let left = self.pop();
+                let value = self.runtime.binary(operator, left, right)?;
+                self.push(value);
+            }
+            Op::Unary(operator) => {
+                let right = self.pop();
+                let value = self.runtime.unary(operator, right)?;
+                self.push(value);
+            }
+            Op::Truthy => {
+                let value = self.pop();
+                self.push(Value::BooleanValue(self.runtime.is_truthy(&value)));
+            }
+            Op::Print => {
+                let value = self.pop();
+                self.runtime.outputter.output_value(&value)?;
+            }
+            Op::Jump(target) => return Ok(Step::Jump(*target as usize)),
+            Op::JumpIfFalse(target) => {
+                let condition = self.pop();
+                if !self.runtime.is_truthy(&condition) {
+                    return Ok(Step::Jump(*target as usize));
+                }
+            }
+            Op::JumpIfFalseOrPop(target) => {
+                if !self.runtime.is_truthy(self.runtime.vm_stack.values.last().unwrap()) {
+                    return Ok(Step::Jump(*target as usize));
+                }
+                self.pop();
+            }
+            Op::JumpIfTrueOrPop(target) => {
+                if self.runtime.is_truthy(self.runtime.vm_stack.values.last().unwrap()) {
+                    return Ok(Step::Jump(*target as usize));
+                }
+                self.pop();
+            }
+            Op::Call(count) => return self.call_value(*count as usize, chunk.spans[ip]),
+            Op::Closure(index) => {
+                let function = self.closure(&chunk.functions[*index as usize], false);
+                self.push(Value::Callable(Box::new(function)));
+            }
+            Op::Class(index) => self.class(chunk, *index as usize)?,
+            Op::List(count) => {
+                let elements = self.pop_many(*count as usize);
+                let list = self.runtime.heap.list(elements);
+                self.push(Value::ListValue(list));
+            }
+            Op::Concat(count) => {
+                let parts = self.pop_many(*count as usize);
+                let string = parts.iter().map(Value::to_string).collect();
+                self.push(Value::StringValue(string));
+            }
+            Op::Map(count) => {
+                let values = self.pop_many(2 * *count as usize);
+                let mut entries = LoxMap::new();
+                let mut values = values.into_iter();
+                while let (Some(key), Some(value)) = (values.next(), values.next()) {
+                    entries.insert(MapKey::from_value(&key)?, value);
+                }
+                let map = self.runtime.heap.map(entries);
+                self.push(Value::MapValue(map));
+            }
+            Op::Return => return Ok(Step::Exit(ControlFlow::Return(self.pop()))),
+            Op::Throw => {
+                let value = self.pop();
+                return Err(LoxError::new(LoxErrorKind::Thrown(Box::new(value.clone())), value.to_string()).with_span(chunk.spans[ip]));
+            }
+            Op::Try(index) => return self.try_statement(&chunk.tries[*index as usize]),
+            Op::Break => return Ok(Step::Exit(ControlFlow::Break)),
+            Op::Continue => return Ok(Step::Exit(ControlFlow::Continue)),
+        }
+        Ok(Step::Next)
+    }
+
+    // calls the value below the top `count` values. Functions compiled for the VM (and classes whose
+    // initializer is one) get a new frame over those values, anything else is called by the runtime.
+    fn call_value(&mut self, count: usize, call_site: Span) -> Result<Step, LoxError> {
+        let base = self.runtime.vm_stack.values.len() - count - 1;
+        let compiled = |function: &Function| function.code().is_some();
+        let target = match &self.runtime.vm_stack.values[base] {
+            Value::Callable(callable) => callable.function().filter(|f| compiled(f)).map(|f| (f.clone(), None)),
+            Value::ClassValue(class) => class.initializer().filter(|f| compiled(f)).map(|f| (f.clone(), Some(Rc::clone(class)))),
+            _ => None,
+        };
+        let (function, class) = match target {
+            Some(target) => target,
+            None => {
+                let arguments = self.pop_many(count);
+                let callee = self.pop();
+                let value = self.runtime.call_value(callee, arguments, call_site)?;
+                self.push(value);
+                return Ok(Step::Next);
+            }
+        };
+
+        let (name, arity) = match &class {
+            Some(class) => (class.name(), class.arity()),
+            None => (function.name(), function.arity()),
+        };
+        if count != arity {
+            return Err(tree_walker::arity_error(&name, arity, arity, count));
+        }
+        let this = match class {
+            Some(class) => Some(self.runtime.heap.instance(LoxInstance::new(class))),
+            None => function.code().unwrap().this.clone(),
+        };
+        if let Some(this) = this {
+            self.runtime.vm_stack.values[base] = Value::InstanceValue(this);
+        }
+        self.runtime.call_stack.push(CallFrame { function: name, call_site });
+        let chunk = Rc::clone(&function.code().unwrap().code);
+        self.frames.push(Frame { chunk, ip: 0, base, function: Some(function), is_call: true });
+        Ok(Step::Enter)
+    }
+
+    // what a call to `function` that finished with `flow` evaluates to
+    fn return_value(&self, function: Option<&Function>, base: usize, flow: ControlFlow) -> Value {
+        if function.is_some_and(Function::is_initializer) {
+            return self.runtime.vm_stack.values[base].clone();
+        }
+        match flow {
+            ControlFlow::Return(value) => value,
+            _ => Value::NilValue,
+        }
+    }
+
+    fn closure(&mut self, proto: &FunctionProto, is_initializer: bool) -> Function {
+        let base = self.base();
+        let upvalues = proto.captures.iter().map(|capture| match capture {
+            Capture::Local(slot) => self.capture(base + *slot as usize),
+            Capture::Upvalue(index) => Rc::clone(self.upvalue(*index)),
+        }).collect();
+        Function::compiled(Rc::clone(&proto.declaration), Rc::clone(&proto.code), upvalues, is_initializer)
+    }
+
+    // same as `TreeWalker::visit_class_decl_statement`. If there's a superclass, it's on top of the stack
+    // in a local of its own so the methods can capture it as "super".
+    fn class(&mut self, chunk: &Chunk, index: usize) -> Result<(), LoxError> {
+        let proto = &chunk.classes[index];
+        let superclass = if proto.has_superclass {
+            match self.runtime.vm_stack.values.last().unwrap() {
+                Value::ClassValue(c) => Some(Rc::clone(c)),
+                other => {
+                    return Err(LoxError::new(LoxErrorKind::TypeError, format!("Superclass must be a class, got {}", other.type_name())))
+                }
+            }
+        } else {
+            None
+        };
+
+        let mut methods = HashMap::new();
+        for method in &proto.methods {
+            let is_initializer = method.declaration.name.lexeme == "init";
+            methods.insert(method.declaration.name.lexeme.to_owned(), self.closure(method, is_initializer));
+        }
+        if superclass.is_some() {
+            let height = self.runtime.vm_stack.values.len() - 1;
+            self.unwind(height);
+        }
+        let class = self.runtime.heap.class(LoxClass::new(proto.name.to_owned(), methods, superclass));
+        self.push(Value::ClassValue(class));
+        Ok(())
+    }
+
+    // same as `TreeWalker::visit_try_statement`, except breaking out of a loop
+    // that's in this chunk has to jump to the end of it
+    fn try_statement(&mut self, proto: &TryProto) -> Result<Step, LoxError> {
+        let height = self.runtime.vm_stack.values.len();
+        let mut result = self.run_block(&proto.body, height);
+        if let Some(body) = &proto.catch {
+            result = match result {
+                Err(error) if error.is_catchable() => {
+                    let value = self.runtime.exception_value(error);
+                    self.push(value);
+                    self.run_block(body, height)
+                }
+                other => other,
+            };
+        }
+        if let Some(finally) = &proto.finally {
+            match self.run_block(finally, height)? {
+                ControlFlow::Normal => {},
+                exit => result = Ok(exit),
+            }
+        }
+        match (result?, proto.exit) {
+            (ControlFlow::Normal, _) => Ok(Step::Next),
+            (ControlFlow::Break, Some(exit)) => {
+                self.unwind(self.base() + exit.locals as usize);
+                Ok(Step::Jump(exit.break_target as usize))
+            }
+            (ControlFlow::Continue, Some(exit)) => {
+                self.unwind(self.base() + exit.locals as usize);
+                Ok(Step::Jump(exit.continue_target as usize))
+            }
+            (flow, _) => Ok(Step::Exit(flow)),
+        }
+    }
+
+    // runs a block of a try statement in the current call frame, then drops the locals it declared
+    fn run_block(&mut self, chunk: &Rc<Chunk>, height: usize) -> Result<ControlFlow, LoxError> {
+        let frame = self.frames.last().unwrap();
+        let block = Frame { chunk: Rc::clone(chunk), ip: 0, base: frame.base, function: frame.function.clone(), is_call: false };
+        self.frames.push(block);
+        let result = self.run();
+        self.unwind(height);
+        result
+    }
+
+    // the upvalue for the variable in `slot`, shared by every closure that captures it
+    fn capture(&mut self, slot: usize) -> UpvalueRef {
+        let open = &mut self.runtime.vm_stack.open_upvalues;
+        if let Some(upvalue) = open.iter().find(|upvalue| matches!(*upvalue.borrow(), Upvalue::Open(s) if s == slot)) {
+            return Rc::clone(upvalue);
+        }
+        let upvalue = self.runtime.heap.upvalue(Upvalue::Open(slot));
+        self.runtime.vm_stack.open_upvalues.push(Rc::clone(&upvalue));
+        upvalue
+    }
+
+    // drops everything on the stack above `height`, first closing the upvalues that point there
+    fn unwind(&mut self, height: usize) {
+        let Stack { values, open_upvalues } = &mut self.runtime.vm_stack;
+        open_upvalues.retain(|upvalue| {
+            let mut upvalue = upvalue.borrow_mut();
+            match *upvalue {
+                Upvalue::Open(slot) if slot >= height => {
+                    *upvalue = Upvalue::Closed(mem::replace(&mut values[slot], Value::NilValue));
+                    false
+                }
+                _ => true,
+            }
+        });
+        values.truncate(height);
+    }
+
+    fn base(&self) -> usize {
+        self.frames.last().unwrap().base
+    }
+
+    // only compiled functions have upvalues, and only their code uses them
+    fn upvalue(&self, index: u32) -> &UpvalueRef {
+        let function = self.frames.last().unwrap().function.as_ref().unwrap();
+        &function.code().unwrap().upvalues[index as usize]
+    }
+
+    fn push(&mut self, value: Value) {
+        self.runtime.vm_stack.values.push(value);
+    }
+
+    // the compiler only pops what it pushed, so the stack can't be empty
+    fn pop(&mut self) -> Value {
+        self.runtime.vm_stack.values.pop().unwrap()
+    }
+
+    fn pop_many(&mut self, count: usize) -> Vec<Value> {
+        let values = &mut self.runtime.vm_stack.values;
+        values.split_off(values.len() - count)
+    }
+
+    fn peek(&self) -> Value {
+        self.runtime.vm_stack.values.last().unwrap().clone()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::{interpreter::{Backend, Interpreter}, output::Recorder};
+
+    fn run(backend: Backend, source: &str) -> (Vec<String>, Vec<String>) {
+        let recorder = Recorder::new();
+        let mut lox = Interpreter::with_output(recorder.clone());
+        lox.set_backend(backend);
+        let errors = match lox.run_source(source) {
+            Ok(()) => Vec::new(),
+            Err(errors) => errors.iter().map(|e| format!("{} {:?} {:?}", e, e.span, e.backtrace.iter().map(|f| (&f.function, f.span)).collect::<Vec<_>>())).collect(),
+        };
+        (recorder.outputted(), errors)
+    }
+
+    #[test]
+    fn errors_match_the_tree_walker() {
+        let programs = [
+            "var a = 1;\nprint a + \"b\";",
+            "print nope;",
+            "fun inner(x) {\n  return x + nil;\n}\nfun outer() { return inner(1); }\nouter();",
+            "class A {\n  init() { this.x = -\"a\"; }\n}\nfun make() {\n  return A();\n}\nmake();",
+            "var list = [1, 2];\nprint list[5];",
+            "print 1;\n{ var a = 1; print a(); }\nprint 2;",
+            "class A < nil {}",
+            "throw {\"a\": 1};",
+        ];
+        for program in programs {
+            assert_eq!(run(Backend::Vm, program), run(Backend::TreeWalker, program), "{}", program);
+        }
+    }
+
+    #[test]
+    fn loop_exits_leave_try_blocks() {
+        let program = "
+            for (var i = 0; i < 5; i = i + 1) {
+                var scoped = i;
+                try {
+                    if (i == 1) continue;
+                    if (i == 3) break;
+                    try { print scoped; } finally { print \"inner\"; }
+                } finally {
+                    print \"outer\";
+                }
+            }
+            fun f() {
+                while (true) { try { return \"returned\"; } finally { print \"cleanup\"; } }
+            }
+            print f();
+            print i;
+        ";
+        let (output, errors) = run(Backend::Vm, program);
+        assert_eq!(output, vec!["0", "inner", "outer", "outer", "2", "inner", "outer", "outer", "cleanup", "returned"]);
+        // the scopes opened by the loop were closed, so `i` isn't defined
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].starts_with("NameError"), "{}", errors[0]);
+    }
+
+    #[test]
+    fn closures_share_captured_locals() {
+        let program = "
+            var get; var set;
+            {
+                var shared = 1;
+                fun g() { return shared; }
+                fun s(value) { shared = value; }
+                get = g; set = s;
+                s(2);
+                print g();
+            }
+            set(3);
+            print get();
+            fun counter() { var n = 0; fun inc() { n = n + 1; return n; } return inc; }
+            var c = counter();
+            c();
+            print c();
+        ";
+        let (output, errors) = run(Backend::Vm, program);
+        assert_eq!(output, vec!["2", "3", "2"]);
+        assert!(errors.is_empty(), "{:?}", errors);
+    }
+
+    #[test]
+    fn backends_share_globals_and_functions() {
+        let mut lox = Interpreter::new();
+        lox.run_source("fun walked(x) { return x + 1; }").unwrap();
+        lox.set_backend(Backend::Vm);
+        lox.run_source("fun compiled(x) { return walked(x) * 2; } class Point { init(x) { this.x = x; } }").unwrap();
+        assert_eq!(lox.eval_expression("compiled(2) + Point(1).x").unwrap().to_string(), "7");
+        lox.set_backend(Backend::TreeWalker);
+        assert_eq!(lox.eval_expression("compiled(4)").unwrap().to_string(), "10");
+    }
+}